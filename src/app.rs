@@ -1,18 +1,38 @@
-use crate::components::{Direction, Enemy, FlashMask, Food, Player, Position, Size, Snake};
+use crate::components::{
+    ButtonAction, DifficultyText, Direction, Enemy, FlashMask, Food, LabelText, Player, Position,
+    Size, Snake,
+};
 use crate::constants::{
-    in_arena, in_enemy_arena, ARENA_HEIGHT, ARENA_WIDTH, BODY_SIZE, DIRECTION_WEIGHT,
-    ENEMY_BODY_COLOR, ENEMY_HEAD_COLOR, FOOD_COLOR, FOOD_SIZE, HEAD_SIZE, HOVERED_BUTTON,
-    INITIAL_HEIGHT, INITIAL_WIDTH, NORMAL_BUTTON, PLAYER_BODY_COLOR, PLAYER_HEAD_COLOR,
-    PRESSED_BUTTON, SNAKE_SPEED, WORLD_COLOR,
+    ARTIFACT_DIR, DIGIT_SIZE, HEATMAP_SCALE, HIGHLIGHT_TEXT_COLOR, HOVERED_BUTTON, INITIAL_HEIGHT,
+    INITIAL_WIDTH, NORMAL_BUTTON, PRESSED_BUTTON, TEXT_COLOR,
+};
+use crate::digit_image;
+use crate::drawings::DrawingLog;
+use crate::inference::DigitRecognizer;
+use crate::leaderboard::{Leaderboard, LeaderboardEntry};
+use crate::preprocess::Preprocess;
+use crate::replay::Replay;
+use crate::resources::{
+    Arena, CounterSetting, DigitModelState, DivergedReplays, ExportSetting, LabelInput, MenuData,
+    PlayerInput, RecordSetting, ReplayPlayback, ScoreScreenData, SeedSetting,
 };
-use crate::events::{EnemyDieEvent, GrowthEvent};
-use crate::resources::{CounterSetting, FoodCenter, IntervalSetting, MenuData, PlayerScore};
-use bevy::prelude::*;
-use rand::{
-    distributions::{Distribution, WeightedIndex},
-    Rng,
+use crate::settings::GameSettings;
+use crate::simulation::{SimSnake, Simulation, StepInput};
+use bevy::{
+    image::ImageSampler,
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+    tasks::AsyncComputeTaskPool,
+    window::{MonitorSelection, WindowMode},
+};
+use std::{
+    path::PathBuf,
+    process::ExitCode,
+    time::{SystemTime, UNIX_EPOCH},
 };
-use std::time::{Duration, Instant};
 
 #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, States)]
 enum GameState {
@@ -23,109 +43,259 @@ enum GameState {
     ShowScore,
 }
 
-pub fn play() {
-    App::new()
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
-            primary_window: Some(Window {
-                title: "snake-game".into(),
-                name: Some("snake.app".into()),
-                resolution: (INITIAL_WIDTH, INITIAL_HEIGHT).into(),
-                enabled_buttons: bevy::window::EnabledButtons {
-                    maximize: false,
-                    ..default()
-                },
+pub struct PlayOptions {
+    pub settings: GameSettings,
+    pub fullscreen: bool,
+    pub record_dir: Option<PathBuf>,
+    pub export_dir: Option<PathBuf>,
+    pub replay: Option<Replay>,
+    pub leaderboard: Leaderboard,
+    pub drawings: DrawingLog,
+}
+
+/// 検証に失敗したリプレイがあれば FAILURE を返す
+pub fn play(options: PlayOptions) -> ExitCode {
+    let mut app = App::new();
+    app.add_plugins(DefaultPlugins.set(WindowPlugin {
+        primary_window: Some(Window {
+            title: "snake-game".into(),
+            name: Some("snake.app".into()),
+            resolution: (INITIAL_WIDTH, INITIAL_HEIGHT).into(),
+            mode: if options.fullscreen {
+                WindowMode::BorderlessFullscreen(MonitorSelection::Primary)
+            } else {
+                WindowMode::Windowed
+            },
+            enabled_buttons: bevy::window::EnabledButtons {
+                maximize: false,
                 ..default()
-            }),
+            },
             ..default()
-        }))
-        .init_state::<GameState>()
-        .add_systems(Startup, setup_camera)
+        }),
+        ..default()
+    }));
+    // リプレイはメニューを飛ばしてすぐに再生する
+    match options.replay {
+        Some(replay) => {
+            app.insert_state(GameState::Playing)
+                .insert_resource(replay.config().arena)
+                .insert_resource(ReplayPlayback::new(replay));
+        }
+        None => {
+            app.init_state::<GameState>()
+                .insert_resource(options.settings.arena());
+        }
+    }
+    app.add_systems(Startup, (setup_camera, load_model))
+        .add_systems(Update, poll_model)
         .add_systems(OnEnter(GameState::Menu), setup_menu)
-        .add_systems(Update, menu)
+        .add_systems(Update, button_system)
         .add_systems(OnExit(GameState::Menu), cleanup_menu)
-        .add_systems(OnEnter(GameState::Playing), spawn_player)
+        .add_systems(OnEnter(GameState::Playing), start_game)
         .add_systems(
             Update,
-            (
-                spawn_enemy,
-                snake_movement_input.before(snake_movement),
-                snake_movement,
-                (snake_growth, enemy_die).after(snake_movement),
-                food_spawner,
-            )
-                .run_if(in_state(GameState::Playing)),
+            (snake_movement_input, sync_snakes, sync_foods).run_if(in_state(GameState::Playing)),
+        )
+        .add_systems(
+            FixedUpdate,
+            snake_movement.run_if(in_state(GameState::Playing)),
+        )
+        .add_systems(
+            FixedUpdate,
+            flash_mask.run_if(in_state(GameState::GameOver)),
         )
-        .add_systems(Update, flash_mask.run_if(in_state(GameState::GameOver)))
         .add_systems(OnExit(GameState::GameOver), game_over)
-        .add_systems(Update, show_score.run_if(in_state(GameState::ShowScore)))
+        .add_systems(OnEnter(GameState::ShowScore), show_score)
+        .add_systems(
+            Update,
+            label_input.run_if(in_state(GameState::ShowScore).and(resource_exists::<LabelInput>)),
+        )
+        .add_systems(OnExit(GameState::ShowScore), cleanup_score)
         .add_systems(PostUpdate, (position_translation, size_scaling))
-        .insert_resource(ClearColor(WORLD_COLOR))
-        .insert_resource(IntervalSetting::<Player>::default())
-        .insert_resource(IntervalSetting::<Enemy>::default())
-        .insert_resource(IntervalSetting::<Food>::default())
-        .insert_resource(IntervalSetting::<FlashMask>::default())
-        .insert_resource(CounterSetting::<Enemy>::default())
+        .add_systems(Last, fail_on_divergence)
+        .insert_resource(ClearColor(GameSettings::color(
+            options.settings.colors.world,
+        )))
+        .insert_resource(Time::<Fixed>::from_duration(options.settings.tick()))
         .insert_resource(CounterSetting::<FlashMask>::default())
-        .insert_resource(FoodCenter::default())
-        .insert_resource(PlayerScore::default())
-        .add_event::<GrowthEvent>()
-        .add_event::<EnemyDieEvent>()
-        .run();
+        .insert_resource(PlayerInput::default())
+        .insert_resource(SeedSetting(options.settings.seed))
+        .insert_resource(RecordSetting(options.record_dir))
+        .insert_resource(ExportSetting(options.export_dir))
+        .insert_resource(options.settings)
+        .insert_resource(options.leaderboard)
+        .insert_resource(options.drawings)
+        .init_resource::<Simulation>()
+        .init_resource::<DivergedReplays>();
+    if app.run().is_success() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+// ウィンドウを閉じて終了するとき、検証に失敗したリプレイがあればエラー終了にする
+fn fail_on_divergence(diverged: Res<DivergedReplays>, mut exits: ResMut<Events<AppExit>>) {
+    if diverged.0 > 0 && !exits.is_empty() {
+        exits.send(AppExit::error());
+    }
 }
 
 fn setup_camera(mut commands: Commands) {
     commands.spawn(Camera2d);
 }
 
-fn setup_menu(mut commands: Commands) {
+// 起動を待たせないよう、モデルは裏で読み込む
+fn load_model(mut commands: Commands) {
+    let task = AsyncComputeTaskPool::get().spawn(async { DigitRecognizer::load(ARTIFACT_DIR) });
+    commands.insert_resource(DigitModelState::Loading(task));
+}
+
+fn poll_model(mut model_state: ResMut<DigitModelState>) {
+    model_state.poll();
+}
+
+fn spawn_text(parent: &mut ChildBuilder, text: impl Into<String>, font_size: f32) {
+    spawn_colored_text(parent, text, font_size, TEXT_COLOR);
+}
+
+fn spawn_colored_text(
+    parent: &mut ChildBuilder,
+    text: impl Into<String>,
+    font_size: f32,
+    color: Color,
+) {
+    parent.spawn((
+        Text::new(text),
+        TextFont {
+            font_size,
+            ..default()
+        },
+        TextColor(color),
+    ));
+}
+
+fn spawn_button(parent: &mut ChildBuilder, label: &str, action: ButtonAction) {
+    parent
+        .spawn((
+            Button,
+            action,
+            Node {
+                width: Val::Px(150.),
+                height: Val::Px(65.),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            BackgroundColor(NORMAL_BUTTON),
+        ))
+        .with_children(|parent| spawn_text(parent, label, 33.0));
+}
+
+// highlight は今のゲームが入った順位
+fn spawn_leaderboard(
+    parent: &mut ChildBuilder,
+    leaderboard: &Leaderboard,
+    highlight: Option<usize>,
+) {
+    spawn_text(parent, "High scores", 24.0);
+    if leaderboard.entries().is_empty() {
+        spawn_text(parent, "no games yet", 16.0);
+    }
+    for (i, entry) in leaderboard.entries().iter().enumerate() {
+        let color = if highlight == Some(i) {
+            HIGHLIGHT_TEXT_COLOR
+        } else {
+            TEXT_COLOR
+        };
+        spawn_colored_text(
+            parent,
+            format!("{:>2}. {}", i + 1, entry.summary()),
+            16.0,
+            color,
+        );
+    }
+}
+
+fn difficulty_label(settings: &GameSettings) -> String {
+    let name = settings
+        .selected_difficulty()
+        .map_or("-", |difficulty| difficulty.name.as_str());
+    format!("Difficulty: {name}")
+}
+
+fn setup_menu(mut commands: Commands, settings: Res<GameSettings>, leaderboard: Res<Leaderboard>) {
     let button_entity = commands
         .spawn(Node {
             width: Val::Percent(100.),
             height: Val::Percent(100.),
+            flex_direction: FlexDirection::Column,
             justify_content: JustifyContent::Center,
             align_items: AlignItems::Center,
+            row_gap: Val::Px(8.),
             ..default()
         })
         .with_children(|parent| {
-            parent
-                .spawn((
-                    Button,
-                    Node {
-                        width: Val::Px(150.),
-                        height: Val::Px(65.),
-                        justify_content: JustifyContent::Center,
-                        align_items: AlignItems::Center,
+            spawn_button(parent, "Play", ButtonAction::Play);
+            if !settings.difficulties.is_empty() {
+                parent.spawn((
+                    Text::new(difficulty_label(&settings)),
+                    TextFont {
+                        font_size: 20.0,
                         ..default()
                     },
-                    BackgroundColor(NORMAL_BUTTON),
-                ))
-                .with_children(|parent| {
-                    parent.spawn((
-                        Text::new("Play"),
-                        TextFont {
-                            font_size: 33.0,
-                            ..default()
-                        },
-                        TextColor(Color::srgb(0.9, 0.9, 0.9)),
-                    ));
-                });
+                    TextColor(TEXT_COLOR),
+                    DifficultyText,
+                ));
+                parent
+                    .spawn(Node {
+                        column_gap: Val::Px(8.),
+                        ..default()
+                    })
+                    .with_children(|parent| {
+                        for (i, difficulty) in settings.difficulties.iter().enumerate() {
+                            spawn_button(parent, &difficulty.name, ButtonAction::Difficulty(i));
+                        }
+                    });
+            }
+            spawn_leaderboard(parent, &leaderboard, None);
         })
         .id();
     commands.insert_resource(MenuData { button_entity });
 }
 
-fn menu(
+fn button_system(
+    mut commands: Commands,
+    sim: Res<Simulation>,
+    mut settings: ResMut<GameSettings>,
+    difficulty_text: Option<Single<&mut Text, With<DifficultyText>>>,
     mut next_state: ResMut<NextState<GameState>>,
     mut interaction_query: Query<
-        (&Interaction, &mut BackgroundColor),
+        (&Interaction, &ButtonAction, &mut BackgroundColor),
         (Changed<Interaction>, With<Button>),
     >,
 ) {
-    for (interaction, mut color) in &mut interaction_query {
+    for (interaction, action, mut color) in &mut interaction_query {
         match *interaction {
             Interaction::Pressed => {
                 *color = PRESSED_BUTTON.into();
-                next_state.set(GameState::Playing);
+                match action {
+                    ButtonAction::Play => next_state.set(GameState::Playing),
+                    ButtonAction::Replay => {
+                        commands
+                            .insert_resource(ReplayPlayback::new(Replay::from_simulation(&sim)));
+                        next_state.set(GameState::Playing);
+                    }
+                    ButtonAction::Menu => next_state.set(GameState::Menu),
+                    ButtonAction::Difficulty(i) => {
+                        settings.difficulty = Some(settings.difficulties[*i].name.clone());
+                        if let Some(mut text) = difficulty_text {
+                            text.0 = difficulty_label(&settings);
+                        }
+                        // 押せるのは 1 フレームに 1 つだけ
+                        return;
+                    }
+                }
             }
             Interaction::Hovered => {
                 *color = HOVERED_BUTTON.into();
@@ -141,19 +311,23 @@ fn cleanup_menu(mut commands: Commands, menu_data: Res<MenuData>) {
     commands.entity(menu_data.button_entity).despawn_recursive();
 }
 
-fn size_scaling(window: Query<&Window>, mut q: Query<(&Size, &mut Transform)>) {
+fn size_scaling(window: Query<&Window>, arena: Res<Arena>, mut q: Query<(&Size, &mut Transform)>) {
     let window = window.single();
     let window_size = window.width().min(window.height());
     for (sprite_size, mut transform) in q.iter_mut() {
         transform.scale = Vec3::new(
-            sprite_size.w() / ARENA_WIDTH as f32 * window_size,
-            sprite_size.h() / ARENA_HEIGHT as f32 * window_size - 6.,
+            sprite_size.w() / arena.width() as f32 * window_size,
+            sprite_size.h() / arena.height() as f32 * window_size - 6.,
             1.0,
         );
     }
 }
 
-fn position_translation(window: Query<&Window>, mut q: Query<(&Position, &mut Transform)>) {
+fn position_translation(
+    window: Query<&Window>,
+    arena: Res<Arena>,
+    mut q: Query<(&Position, &mut Transform)>,
+) {
     fn convert(pos: f32, bound_window: f32, bound_game: f32) -> f32 {
         let tile_size = bound_window / bound_game;
         pos / bound_game * bound_window - (bound_window / 2.) + (tile_size / 2.)
@@ -161,17 +335,20 @@ fn position_translation(window: Query<&Window>, mut q: Query<(&Position, &mut Tr
     let window = window.single();
     for (pos, mut transform) in q.iter_mut() {
         transform.translation = Vec3::new(
-            convert(pos.x() as f32, window.width(), ARENA_WIDTH as f32),
-            convert(pos.y() as f32, window.height(), ARENA_HEIGHT as f32) - 3.,
+            convert(pos.x() as f32, window.width(), arena.width() as f32),
+            convert(pos.y() as f32, window.height(), arena.height() as f32) - 3.,
             0.0,
         );
     }
 }
 
-fn spawn_player(
+fn start_game(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut sim: ResMut<Simulation>,
+    mut time: ResMut<Time<Fixed>>,
+    settings: Res<GameSettings>,
+    seed_setting: Res<SeedSetting>,
+    playback: Option<Res<ReplayPlayback>>,
 ) {
     commands
         .spawn((
@@ -184,90 +361,80 @@ fn spawn_player(
         .insert(FlashMask)
         .insert(Size::rectangle(INITIAL_WIDTH, INITIAL_HEIGHT));
 
-    let mut snake = Snake::default();
-    snake.add(
-        commands
-            .spawn((
-                Mesh2d(meshes.add(Circle::default())),
-                MeshMaterial2d(materials.add(PLAYER_HEAD_COLOR)),
-            ))
-            .insert(Position::new(
-                ARENA_WIDTH as i32 / 2,
-                ARENA_HEIGHT as i32 / 2,
-            ))
-            .insert(Size::square(HEAD_SIZE))
-            .id(),
-    );
-    snake.add(
-        commands
-            .spawn((
-                Mesh2d(meshes.add(Circle::default())),
-                MeshMaterial2d(materials.add(PLAYER_BODY_COLOR)),
-            ))
-            .insert(Position::new(
-                ARENA_WIDTH as i32 / 2,
-                ARENA_HEIGHT as i32 / 2 - 1,
-            ))
-            .insert(Size::square(BODY_SIZE))
-            .id(),
-    );
-    commands.spawn(snake).insert(Player).insert(Position::new(
-        ARENA_WIDTH as i32 / 2,
-        ARENA_HEIGHT as i32 / 2,
-    ));
+    *sim = match playback {
+        Some(playback) => playback.replay().simulation(),
+        None => Simulation::new(settings.sim_config(), seed_setting.next_seed()),
+    };
+    // リプレイの後は設定と盤面の大きさが違うことがある
+    commands.insert_resource(sim.config().arena);
+    time.set_timestep(sim.tick_duration());
 }
 
-fn spawn_enemy(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-    mut counter: ResMut<CounterSetting<Enemy>>,
+fn snake_movement_input(keyboard_input: Res<ButtonInput<KeyCode>>, mut input: ResMut<PlayerInput>) {
+    if keyboard_input.pressed(KeyCode::ArrowUp) {
+        input.set(Direction::UP);
+    } else if keyboard_input.pressed(KeyCode::ArrowDown) {
+        input.set(Direction::DOWN);
+    } else if keyboard_input.pressed(KeyCode::ArrowRight) {
+        input.set(Direction::RIGHT);
+    } else if keyboard_input.pressed(KeyCode::ArrowLeft) {
+        input.set(Direction::LEFT);
+    }
+}
+
+fn snake_movement(
+    mut sim: ResMut<Simulation>,
+    mut time: ResMut<Time<Fixed>>,
+    mut input: ResMut<PlayerInput>,
+    mut playback: Option<ResMut<ReplayPlayback>>,
+    record: Res<RecordSetting>,
+    export: Res<ExportSetting>,
+    mut diverged: ResMut<DivergedReplays>,
+    mut next_state: ResMut<NextState<GameState>>,
 ) {
-    if !counter.less_than(30) {
+    // 状態が切り替わるのは次のフレームなので、同じフレームの残りの tick では何もしない
+    if sim.is_over() {
         return;
     }
-
-    let mut rng = rand::thread_rng();
-    let mut px = rng.gen_range(-1..3);
-    let mut py = rng.gen_range(-1..3);
-    while (px == 0 || px == 1) && (py == 0 || py == 1) {
-        px = rng.gen_range(-1..3);
-        py = rng.gen_range(-1..3);
+    let player = match playback.as_mut() {
+        Some(playback) => playback.next_input(),
+        None => input.take(),
+    };
+    sim.step(StepInput { player });
+    // 難易度の段階で速さが変わる
+    if time.timestep() != sim.tick_duration() {
+        time.set_timestep(sim.tick_duration());
     }
-    let x = px * (ARENA_WIDTH as i32 / 2) + rng.gen_range(0..ARENA_WIDTH) as i32;
-    let y = (ARENA_HEIGHT as i32 - 4)
-        .min(py * (ARENA_HEIGHT as i32 / 2) + rng.gen_range(0..ARENA_HEIGHT) as i32); // 体が下に続くため
-
-    let mut snake = Snake::default();
-    snake.add(
-        commands
-            .spawn((
-                Mesh2d(meshes.add(Circle::default())),
-                MeshMaterial2d(materials.add(ENEMY_HEAD_COLOR)),
-            ))
-            .insert(Position::new(x, y))
-            .insert(Size::square(HEAD_SIZE))
-            .id(),
-    );
-    for i in 1..4 {
-        snake.add(
-            commands
-                .spawn((
-                    Mesh2d(meshes.add(Circle::default())),
-                    MeshMaterial2d(materials.add(ENEMY_BODY_COLOR)),
-                ))
-                .insert(Position::new(x, y - i))
-                .insert(Size::square(BODY_SIZE))
-                .id(),
-        );
+    if !sim.is_over() {
+        return;
     }
+    next_state.set(GameState::GameOver);
 
-    commands
-        .spawn(snake)
-        .insert(Enemy::default())
-        .insert(Position::new(x, y));
-
-    counter.increment();
+    if let Some(mut playback) = playback {
+        if let Err(err) = playback.replay().verify(&sim) {
+            eprintln!("{err}");
+            playback.set_diverged(err.to_string());
+            diverged.0 += 1;
+        }
+        return;
+    }
+    // 同じゲームのリプレイと画像が同じ名前で並ぶようにする
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let stem = format!("{}-{}", secs, sim.seed());
+    if let Some(dir) = &record.0 {
+        let path = dir.join(format!("{stem}.replay"));
+        if let Err(err) = Replay::from_simulation(&sim).save(&path) {
+            eprintln!("failed to save replay to {}: {err}", path.display());
+        }
+    }
+    if let Some(dir) = &export.0 {
+        if let Err(err) = digit_image::export(dir, &stem, sim.score()) {
+            eprintln!("failed to export drawing to {}: {err}", dir.display());
+        }
+    }
 }
 
 fn spawn_body(
@@ -276,245 +443,135 @@ fn spawn_body(
     materials: &mut ResMut<Assets<ColorMaterial>>,
     position: Position,
     scale: Size,
-    is_player: bool,
+    color: Color,
 ) -> Entity {
     commands
         .spawn((
             Mesh2d(meshes.add(Circle::default())),
-            MeshMaterial2d(materials.add(if is_player {
-                PLAYER_BODY_COLOR
-            } else {
-                ENEMY_BODY_COLOR
-            })),
+            MeshMaterial2d(materials.add(color)),
         ))
         .insert(position)
         .insert(scale)
         .id()
 }
 
-fn snake_movement_input(
-    keyboard_input: Res<ButtonInput<KeyCode>>,
-    mut snakes: Query<(&mut Snake, Entity)>,
-    mut enemies: Query<&mut Enemy>,
-    pos: Query<&Position, With<Enemy>>,
-    center: Res<FoodCenter>,
+fn spawn_snake(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<ColorMaterial>>,
+    settings: &GameSettings,
+    sim_snake: &SimSnake,
 ) {
-    let mut rng = rand::thread_rng();
-    for (mut snake, ent) in snakes.iter_mut() {
-        if let Ok(mut enemy) = enemies.get_mut(ent) {
-            if enemy.get() > snake.len() {
-                let diff = center.get_pos() - *pos.get(ent).unwrap();
-                let food_weight = [
-                    if diff.y() > 0 { diff.y() } else { 0 },
-                    if diff.x() > 0 { diff.x() } else { 0 },
-                    if diff.y() < 0 { -diff.y() } else { 0 },
-                    if diff.x() < 0 { -diff.x() } else { 0 },
-                ];
-                // 自分が向いている方向
-                let dir = snake.get_dir().get_num();
-                // (今の向きを基準として)次に向く方向の確率
-                let dist = WeightedIndex::new(
-                    DIRECTION_WEIGHT
-                        .iter()
-                        .enumerate()
-                        .map(|(i, &w)| {
-                            if w == 0 {
-                                0
-                            } else {
-                                food_weight[(i + 4 - dir as usize) % 4] + w
-                            }
-                        })
-                        .collect::<Vec<i32>>(),
-                )
-                .unwrap();
-                snake.set_dir(Direction::num2dir(dir + dist.sample(&mut rng) as u8));
-                enemy.reset();
-            }
+    let (head_color, body_color) = if sim_snake.is_player() {
+        (settings.colors.player_head, settings.colors.player_body)
+    } else {
+        (settings.colors.enemy_head, settings.colors.enemy_body)
+    };
+
+    let mut snake = Snake::new(sim_snake.id());
+    for (i, &pos) in sim_snake.body().iter().enumerate() {
+        let (size, color) = if i == 0 {
+            (settings.sizes.head, head_color)
         } else {
-            let dir: Direction = if keyboard_input.pressed(KeyCode::ArrowUp) {
-                Direction::UP
-            } else if keyboard_input.pressed(KeyCode::ArrowDown) {
-                Direction::DOWN
-            } else if keyboard_input.pressed(KeyCode::ArrowRight) {
-                Direction::RIGHT
-            } else if keyboard_input.pressed(KeyCode::ArrowLeft) {
-                Direction::LEFT
-            } else {
-                snake.get_dir()
-            };
-            if dir != snake.get_dir().opposite() {
-                snake.set_dir(dir);
-            }
-        }
+            (settings.sizes.body, body_color)
+        };
+        snake.add(spawn_body(
+            commands,
+            meshes,
+            materials,
+            pos,
+            Size::square(size),
+            GameSettings::color(color),
+        ));
+    }
+
+    if sim_snake.is_player() {
+        commands.spawn(snake).insert(Player);
+    } else {
+        commands.spawn(snake).insert(Enemy);
     }
 }
 
-fn snake_movement(
-    mut snakes: Query<(&mut Snake, Entity)>,
+fn sync_snakes(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    settings: Res<GameSettings>,
+    sim: Res<Simulation>,
+    mut snakes: Query<(Entity, &mut Snake)>,
     mut positions: Query<&mut Position>,
-    player: Query<&Player>,
-    mut enemies: Query<&mut Enemy>,
-    foods: Query<Entity, With<Food>>,
-    mut enemy_die_writer: EventWriter<EnemyDieEvent>,
-    mut growth_writer: EventWriter<GrowthEvent>,
-    mut interval: ResMut<IntervalSetting<Player>>,
-    mut next_state: ResMut<NextState<GameState>>,
-    mut history: ResMut<PlayerScore>,
 ) {
-    let now = Instant::now();
-    if !interval.check(now, Duration::from_millis(600 / SNAKE_SPEED)) {
-        return;
-    }
-    interval.update(now);
-
-    let body_pos = snakes
-        .iter()
-        .map(|(snake, _ent)| {
-            snake
-                .iter()
-                .map(|&body| *positions.get(body).unwrap())
-                .collect::<Vec<Position>>()
-        })
-        .flatten()
-        .collect::<Vec<Position>>();
-
-    let food_pos = foods
-        .iter()
-        .map(|ent| (*positions.get(ent).unwrap(), ent))
-        .collect::<Vec<(Position, Entity)>>();
-
-    for (mut snake, snake_ent) in snakes.iter_mut() {
-        let tail = *positions.get(*snake.get_tail()).unwrap();
-        snake.set_tail_pos(tail);
-        let now_pos = snake
-            .iter()
-            .map(|e| *positions.get(*e).unwrap())
-            .collect::<Vec<Position>>();
-        let head_pos = *positions.get(*snake.get_head()).unwrap() + snake.get_dir().to_pos(1);
-        if let Ok(_) = player.get(snake_ent) {
-            if !in_arena(head_pos.x(), head_pos.y()) {
-                next_state.set(GameState::GameOver);
-                break;
-            }
-            if body_pos.contains(&head_pos) {
-                next_state.set(GameState::GameOver);
-                break;
-            }
-            history.as_mut().add(head_pos);
-        } else {
-            if !in_enemy_arena(head_pos.x(), head_pos.y()) {
-                enemy_die_writer.send(EnemyDieEvent { enemy: snake_ent });
-                continue;
-            }
-            if body_pos.contains(&head_pos) {
-                enemy_die_writer.send(EnemyDieEvent { enemy: snake_ent });
-                continue;
-            }
-            // この個体が直進した長さ
-            if let Ok(mut enemy) = enemies.get_mut(snake_ent) {
-                enemy.increment();
-            }
+    for (ent, mut snake) in snakes.iter_mut() {
+        let Some(sim_snake) = sim.snake(snake.id()) else {
+            snake.despawn(&mut commands);
+            commands.entity(ent).despawn();
+            continue;
+        };
+        for (body, &pos) in snake.iter().zip(sim_snake.body()) {
+            *positions.get_mut(*body).unwrap() = pos;
         }
-        food_pos.iter().for_each(|&(pos, food_ent)| {
-            if head_pos == pos {
-                growth_writer.send(GrowthEvent {
-                    snake: snake_ent,
-                    food: (pos, food_ent),
-                });
-            }
+        let color = GameSettings::color(if sim_snake.is_player() {
+            settings.colors.player_body
+        } else {
+            settings.colors.enemy_body
         });
-        *positions.get_mut(*snake.get_head()).unwrap() = head_pos;
-        now_pos
-            .iter()
-            .zip(snake.iter().skip(1))
-            .for_each(|(pos, body)| {
-                *positions.get_mut(*body).unwrap() = *pos;
-            });
+        for &pos in sim_snake.body().iter().skip(snake.len()) {
+            let body = spawn_body(
+                &mut commands,
+                &mut meshes,
+                &mut materials,
+                pos,
+                Size::square(settings.sizes.body),
+                color,
+            );
+            snake.add(body);
+        }
     }
-}
 
-fn food_spawner(
-    mut commands: Commands,
-    mut interval: ResMut<IntervalSetting<Food>>,
-    mut center: ResMut<FoodCenter>,
-) {
-    let now = Instant::now();
-    if !interval.check(now, Duration::from_secs(1)) {
-        return;
+    for sim_snake in sim.snakes() {
+        if !snakes.iter().any(|(_, snake)| snake.id() == sim_snake.id()) {
+            spawn_snake(
+                &mut commands,
+                &mut meshes,
+                &mut materials,
+                &settings,
+                sim_snake,
+            );
+        }
     }
-    interval.update(now);
-
-    let pos = Position::new(
-        (rand::random::<f32>() * ARENA_WIDTH as f32) as i32,
-        (rand::random::<f32>() * ARENA_HEIGHT as f32) as i32,
-    );
-
-    commands
-        .spawn(Sprite {
-            color: FOOD_COLOR,
-            ..default()
-        })
-        .insert(Food)
-        .insert(pos)
-        .insert(Size::square(FOOD_SIZE));
-
-    center.add(&pos);
 }
 
-fn snake_growth(
+fn sync_foods(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-    player: Query<&Player>,
-    mut player_score: ResMut<PlayerScore>,
-    mut snakes: Query<&mut Snake>,
-    mut growth_reader: EventReader<GrowthEvent>,
-    mut center: ResMut<FoodCenter>,
+    settings: Res<GameSettings>,
+    sim: Res<Simulation>,
+    foods: Query<(Entity, &Food)>,
 ) {
-    for event in growth_reader.read() {
-        center.remove(&event.food.0);
-        commands.entity(event.food.1).despawn();
-        let mut snake = snakes.get_mut(event.snake).unwrap();
-        let tail_pos = snake.get_tail_pos();
-        snake.add(spawn_body(
-            &mut commands,
-            &mut meshes,
-            &mut materials,
-            tail_pos,
-            Size::square(BODY_SIZE),
-            player.get(event.snake).is_ok(),
-        ));
-        if player.get(event.snake).is_ok() {
-            player_score.increment();
+    for (ent, food) in foods.iter() {
+        if !sim.foods().any(|sim_food| sim_food.id() == food.0) {
+            commands.entity(ent).despawn();
         }
     }
-}
 
-fn enemy_die(
-    mut commands: Commands,
-    snakes: Query<&Snake>,
-    mut enemy_die_reader: EventReader<EnemyDieEvent>,
-    mut counter: ResMut<CounterSetting<Enemy>>,
-) {
-    for event in enemy_die_reader.read() {
-        snakes.get(event.enemy).unwrap().despawn(&mut commands);
-        commands.entity(event.enemy).despawn();
-        counter.decrement();
+    for sim_food in sim.foods() {
+        if !foods.iter().any(|(_, food)| food.0 == sim_food.id()) {
+            commands
+                .spawn(Sprite {
+                    color: GameSettings::color(settings.colors.food),
+                    ..default()
+                })
+                .insert(Food(sim_food.id()))
+                .insert(sim_food.pos())
+                .insert(Size::square(settings.sizes.food));
+        }
     }
 }
 
 fn flash_mask(
     mut mask: Single<&mut Sprite, With<FlashMask>>,
-    mut interval: ResMut<IntervalSetting<FlashMask>>,
     mut counter: ResMut<CounterSetting<FlashMask>>,
     mut next_state: ResMut<NextState<GameState>>,
 ) {
-    let now = Instant::now();
-    if !interval.check(now, Duration::from_millis(50)) {
-        return;
-    }
-    interval.update(now);
-
     if counter.less_than(10) {
         if counter.is_even() {
             mask.color = Color::linear_rgba(0.8, 0.8, 0.8, 0.6);
@@ -539,11 +596,7 @@ fn game_over(
     snake_entities: Query<Entity, With<Snake>>,
     food_entities: Query<Entity, With<Food>>,
     mask: Single<Entity, With<FlashMask>>,
-    mut player_interval: ResMut<IntervalSetting<Player>>,
-    mut enemy_interval: ResMut<IntervalSetting<Enemy>>,
-    mut food_interval: ResMut<IntervalSetting<Food>>,
-    mut flash_interval: ResMut<IntervalSetting<FlashMask>>,
-    mut enemy_counter: ResMut<CounterSetting<Enemy>>,
+    mut input: ResMut<PlayerInput>,
     mut flash_counter: ResMut<CounterSetting<FlashMask>>,
 ) {
     for snake in snakes.iter() {
@@ -553,16 +606,249 @@ fn game_over(
         commands.entity(ent).despawn();
     }
     commands.entity(*mask).despawn();
-    player_interval.reset();
-    enemy_interval.reset();
-    food_interval.reset();
-    flash_interval.reset();
-    enemy_counter.reset();
+    input.take();
     flash_counter.reset();
 }
 
-fn show_score(player_score: Res<PlayerScore>, mut next_state: ResMut<NextState<GameState>>) {
-    let score = player_score.get_score();
-    println!("{}", score);
-    next_state.set(GameState::Menu);
+// モデルに渡したのと同じ並びで、最大値を白とした濃淡画像にする
+fn heatmap_image(grid: &[[f32; DIGIT_SIZE]; DIGIT_SIZE]) -> Image {
+    let data = digit_image::grayscale(grid)
+        .into_iter()
+        .flat_map(|level| [level, level, level, 255])
+        .collect();
+    let mut image = Image::new(
+        Extent3d {
+            width: DIGIT_SIZE as u32,
+            height: DIGIT_SIZE as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    image.sampler = ImageSampler::nearest();
+    image
+}
+
+fn show_score(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    sim: Res<Simulation>,
+    settings: Res<GameSettings>,
+    playback: Option<Res<ReplayPlayback>>,
+    mut model_state: ResMut<DigitModelState>,
+    mut leaderboard: ResMut<Leaderboard>,
+    drawings: Res<DrawingLog>,
+) {
+    // 読み込みが間に合っていなければここで待つ
+    model_state.wait();
+    let model = match &*model_state {
+        DigitModelState::Ready(model) => Some(model.lock().unwrap()),
+        _ => None,
+    };
+    let score = sim
+        .score()
+        .get_score(model.as_deref(), settings.scoring.confidence_weighted);
+    // 画面にはモデルが実際に見た画像を出す
+    let preprocess = model
+        .as_deref()
+        .map_or_else(Preprocess::default, DigitRecognizer::preprocess);
+    drop(model);
+    // リプレイの結果は記録しない
+    let rank = if playback.is_some() {
+        commands.remove_resource::<ReplayPlayback>();
+        None
+    } else {
+        let rank = leaderboard.insert(LeaderboardEntry {
+            score: score.total,
+            food_count: score.food_count,
+            digits: score.digits,
+            length: sim.player().map_or(0, |player| player.len()),
+            duration_ms: sim.elapsed().as_millis() as u64,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            seed: sim.seed(),
+        });
+        if let Err(err) = leaderboard.save() {
+            eprintln!("failed to save leaderboard: {err}");
+        }
+        rank
+    };
+    let heatmaps =
+        [0, 1].map(|i| images.add(heatmap_image(&preprocess.apply(&sim.score().digit_grid(i)))));
+    // ラベルは保存先があるときだけ聞く。リプレイでは元の対局で聞いているので聞かない
+    let label = (playback.is_none() && drawings.path().is_some())
+        .then(|| LabelInput::new(sim.score(), sim.seed()));
+
+    let root_entity = commands
+        .spawn(Node {
+            width: Val::Percent(100.),
+            height: Val::Percent(100.),
+            flex_direction: FlexDirection::Column,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            row_gap: Val::Px(8.),
+            ..default()
+        })
+        .with_children(|parent| {
+            let title = if playback.is_some() {
+                format!("Replay: {}", score.total)
+            } else {
+                format!("Score {}", score.total)
+            };
+            spawn_text(parent, title, 48.0);
+            if let Some(reason) = playback.as_ref().and_then(|playback| playback.diverged()) {
+                spawn_colored_text(parent, reason, 24.0, HIGHLIGHT_TEXT_COLOR);
+            }
+            spawn_text(
+                parent,
+                format!(
+                    "food {} + digits {} + {}",
+                    score.food_count, score.bonus[0], score.bonus[1]
+                ),
+                24.0,
+            );
+            parent
+                .spawn(Node {
+                    column_gap: Val::Px(24.),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for (i, heatmap) in heatmaps.into_iter().enumerate() {
+                        parent
+                            .spawn(Node {
+                                flex_direction: FlexDirection::Column,
+                                align_items: AlignItems::Center,
+                                row_gap: Val::Px(4.),
+                                ..default()
+                            })
+                            .with_children(|parent| {
+                                parent.spawn((
+                                    ImageNode::new(heatmap),
+                                    Node {
+                                        width: Val::Px(DIGIT_SIZE as f32 * HEATMAP_SCALE),
+                                        height: Val::Px(DIGIT_SIZE as f32 * HEATMAP_SCALE),
+                                        ..default()
+                                    },
+                                ));
+                                let Some(predictions) = &score.predictions else {
+                                    spawn_text(parent, "-", 20.0);
+                                    return;
+                                };
+                                for (rank, (digit, p)) in
+                                    predictions[i].top(3).into_iter().enumerate()
+                                {
+                                    spawn_text(
+                                        parent,
+                                        format!("{digit}  {:>5.1}%", p * 100.),
+                                        if rank == 0 { 20.0 } else { 16.0 },
+                                    );
+                                }
+                            });
+                    }
+                });
+            if let Some(err) = model_state.error() {
+                spawn_text(parent, format!("digits not scored: {err}"), 16.0);
+            }
+            if let Some(rank) = rank {
+                spawn_text(parent, format!("New high score: #{}", rank + 1), 24.0);
+            }
+            if let Some(label) = &label {
+                parent.spawn((
+                    Text::new(label.text()),
+                    TextFont {
+                        font_size: 20.0,
+                        ..default()
+                    },
+                    TextColor(TEXT_COLOR),
+                    LabelText,
+                ));
+            }
+            spawn_text(parent, format!("seed {}", sim.seed()), 16.0);
+            spawn_leaderboard(parent, &leaderboard, rank);
+            parent
+                .spawn(Node {
+                    column_gap: Val::Px(16.),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    spawn_button(parent, "Replay", ButtonAction::Replay);
+                    spawn_button(parent, "Retry", ButtonAction::Play);
+                    spawn_button(parent, "Menu", ButtonAction::Menu);
+                });
+        })
+        .id();
+    commands.insert_resource(ScoreScreenData { root_entity });
+    if let Some(label) = label {
+        commands.insert_resource(label);
+    }
+}
+
+fn label_input(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut label: ResMut<LabelInput>,
+    drawings: Res<DrawingLog>,
+    mut text: Single<&mut Text, With<LabelText>>,
+) {
+    if label.is_saved() {
+        return;
+    }
+    for key in keyboard_input.get_just_pressed() {
+        match key {
+            KeyCode::Backspace => label.pop(),
+            KeyCode::Enter | KeyCode::NumpadEnter => {
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                if let Some(items) = label.take(timestamp) {
+                    match drawings.append(&items) {
+                        Ok(()) => {
+                            if let Some(path) = drawings.path() {
+                                println!("saved labelled drawings to {}", path.display());
+                            }
+                        }
+                        Err(err) => eprintln!("failed to save drawings: {err}"),
+                    }
+                }
+            }
+            key => {
+                if let Some(digit) = digit_key(*key) {
+                    label.push(digit);
+                }
+            }
+        }
+    }
+    if label.is_changed() {
+        text.0 = label.text();
+    }
+}
+
+fn digit_key(key: KeyCode) -> Option<u8> {
+    use KeyCode::*;
+    [
+        (Digit0, Numpad0),
+        (Digit1, Numpad1),
+        (Digit2, Numpad2),
+        (Digit3, Numpad3),
+        (Digit4, Numpad4),
+        (Digit5, Numpad5),
+        (Digit6, Numpad6),
+        (Digit7, Numpad7),
+        (Digit8, Numpad8),
+        (Digit9, Numpad9),
+    ]
+    .iter()
+    .position(|&(digit, numpad)| key == digit || key == numpad)
+    .map(|i| i as u8)
+}
+
+fn cleanup_score(mut commands: Commands, score_screen: Res<ScoreScreenData>) {
+    commands
+        .entity(score_screen.root_entity)
+        .despawn_recursive();
+    commands.remove_resource::<LabelInput>();
 }
@@ -0,0 +1,63 @@
+use std::time::Instant;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::components::Direction;
+use crate::inference::DigitRecognizer;
+use crate::simulation::{SimConfig, Simulation, StepInput};
+
+// 1 ゲームの上限。壁に当たらないまま回り続けることがあるため
+const MAX_TICKS: u64 = 10_000;
+
+// たまにランダムに曲がるだけのプレイヤー
+fn random_input(rng: &mut ChaCha8Rng) -> Option<Direction> {
+    if rng.gen_ratio(1, 8) {
+        Some(Direction::num2dir(rng.gen_range(0..4)))
+    } else {
+        None
+    }
+}
+
+/// ウィンドウなしでゲームを回し、シミュレーションと数字認識の速さを表示する
+pub fn run(config: &SimConfig, seed: u64, games: u32, model: Option<&DigitRecognizer>) {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut ticks = 0;
+    let mut food = 0;
+    let mut finished = Vec::new();
+
+    let start = Instant::now();
+    for game in 0..games {
+        let mut sim = Simulation::new(config.clone(), seed.wrapping_add(game as u64));
+        while !sim.is_over() && sim.tick() < MAX_TICKS {
+            sim.step(StepInput {
+                player: random_input(&mut rng),
+            });
+        }
+        ticks += sim.tick();
+        food += sim.score().food_count() as u64;
+        finished.push(sim);
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "simulation: {games} games, {ticks} ticks in {:.3}s ({:.0} ticks/s), {:.2} food per game (seed: {seed})",
+        elapsed.as_secs_f64(),
+        ticks as f64 / elapsed.as_secs_f64(),
+        food as f64 / games.max(1) as f64,
+    );
+
+    let Some(model) = model else {
+        return;
+    };
+    let start = Instant::now();
+    for sim in finished.iter() {
+        sim.score().get_score(Some(model), false);
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "inference: {} grids in {:.3}s ({:.2} ms per grid)",
+        finished.len() * 2,
+        elapsed.as_secs_f64(),
+        elapsed.as_secs_f64() * 1000. / (finished.len() * 2).max(1) as f64,
+    );
+}
@@ -0,0 +1,442 @@
+use std::{cell::OnceCell, collections::VecDeque, fmt};
+
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    Rng,
+};
+
+use crate::components::{Direction, Position};
+use crate::resources::Arena;
+use crate::simulation::{GameRng, SimConfig, SimFood, SimSnake};
+
+/// 敵が向きを決めるときに見える盤面。tick ごとに作り、全員で共有する
+pub struct WorldView<'a> {
+    pub arena: Arena,
+    pub snakes: &'a [SimSnake],
+    pub foods: &'a [SimFood],
+    // 出ている餌の重心
+    pub food_center: Position,
+    // 1 回の探索で展開してよいマス数。先に探した敵が使い切らないよう、tick の予算を敵の数で割る
+    search_budget: usize,
+    // 敵の動ける範囲のうち体で埋まっているマス。最初に要るときに作る
+    occupied: OnceCell<Vec<bool>>,
+}
+
+impl<'a> WorldView<'a> {
+    pub fn new(
+        arena: Arena,
+        snakes: &'a [SimSnake],
+        foods: &'a [SimFood],
+        food_center: Position,
+        budget: usize,
+    ) -> Self {
+        let enemies = snakes.iter().filter(|snake| !snake.is_player()).count();
+        WorldView {
+            arena,
+            snakes,
+            foods,
+            food_center,
+            search_budget: budget / enemies.max(1),
+            occupied: OnceCell::new(),
+        }
+    }
+
+    // 敵の動ける範囲 (アリーナの 2 倍の広さ) の大きさと左下の隅
+    fn bounds(&self) -> (i32, i32, Position) {
+        let width = self.arena.width() as i32;
+        let height = self.arena.height() as i32;
+        let min = Position::new(-(width / 2), -(height / 2));
+        (3 * width / 2 - min.x(), 3 * height / 2 - min.y(), min)
+    }
+    fn index(&self, pos: Position) -> Option<usize> {
+        if !self.arena.in_enemy_arena(pos.x(), pos.y()) {
+            return None;
+        }
+        let (width, _, min) = self.bounds();
+        let pos = pos - min;
+        Some((pos.y() * width + pos.x()) as usize)
+    }
+    fn occupied(&self) -> &[bool] {
+        self.occupied.get_or_init(|| {
+            let (width, height, _) = self.bounds();
+            let mut occupied = vec![false; width as usize * height as usize];
+            for &pos in self.snakes.iter().flat_map(|snake| snake.body()) {
+                if let Some(i) = self.index(pos) {
+                    occupied[i] = true;
+                }
+            }
+            occupied
+        })
+    }
+
+    // 敵が入ると死ぬマス
+    pub fn is_blocked(&self, pos: Position) -> bool {
+        self.index(pos).is_none_or(|i| self.occupied()[i])
+    }
+    // 向き dir にぶつからずに進めるマス数 (limit まで)
+    pub fn free_run(&self, from: Position, dir: Direction, limit: i32) -> i32 {
+        (1..=limit)
+            .find(|&step| self.is_blocked(from + dir.to_pos(step)))
+            .map_or(limit, |step| step - 1)
+    }
+    pub fn player(&self) -> Option<&SimSnake> {
+        self.snakes.iter().find(|snake| snake.is_player())
+    }
+    pub fn nearest_food(&self, from: Position) -> Option<Position> {
+        self.foods
+            .iter()
+            .map(|food| food.pos())
+            .min_by_key(|&pos| distance(from, pos))
+    }
+
+    /// 幅優先探索で一番近くにたどり着ける餌と、そこまでの向きの列を返す。
+    /// 予算を使い切ったか、どの餌にも届かなければ None
+    pub fn find_food(&self, from: Position) -> Option<(Position, Vec<Direction>)> {
+        if self.foods.is_empty() {
+            return None;
+        }
+        let (width, height, _) = self.bounds();
+        // 来た向き。始点は UP で埋めておくが使わない
+        let mut came = vec![None; width as usize * height as usize];
+        let start = self.index(from)?;
+        came[start] = Some(Direction::UP);
+        let mut queue = VecDeque::from([from]);
+        let mut budget = self.search_budget;
+        while let Some(pos) = queue.pop_front() {
+            if budget == 0 {
+                return None;
+            }
+            budget -= 1;
+            if pos != from && self.foods.iter().any(|food| food.pos() == pos) {
+                return Some((pos, self.trace(&came, from, pos)));
+            }
+            for dir in [
+                Direction::UP,
+                Direction::RIGHT,
+                Direction::DOWN,
+                Direction::LEFT,
+            ] {
+                let next = pos + dir.to_pos(1);
+                let Some(i) = self.index(next) else {
+                    continue;
+                };
+                if came[i].is_none() && !self.occupied()[i] {
+                    came[i] = Some(dir);
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+    // 終点から来た向きを逆にたどる。先頭が最後の一歩
+    fn trace(&self, came: &[Option<Direction>], from: Position, to: Position) -> Vec<Direction> {
+        let mut path = Vec::new();
+        let mut pos = to;
+        while pos != from {
+            let dir = came[self.index(pos).unwrap()].unwrap();
+            path.push(dir);
+            pos -= dir.to_pos(1);
+        }
+        path
+    }
+}
+
+pub fn distance(a: Position, b: Position) -> i32 {
+    let diff = a - b;
+    diff.x().abs() + diff.y().abs()
+}
+
+// 今の向きから見て [直進, 右, 左]
+fn forward_turns(dir: Direction) -> [Direction; 3] {
+    let num = dir.get_num();
+    [
+        dir,
+        Direction::num2dir(num + 1),
+        Direction::num2dir(num + 3),
+    ]
+}
+
+// ぶつからない向きのうち target に一番近づくもの。どこにも行けなければ直進
+fn step_toward(me: &SimSnake, world: &WorldView, target: Position) -> Direction {
+    let head = me.head();
+    forward_turns(me.direction())
+        .into_iter()
+        .filter(|&dir| !world.is_blocked(head + dir.to_pos(1)))
+        .min_by_key(|&dir| distance(head + dir.to_pos(1), target))
+        .unwrap_or(me.direction())
+}
+
+/// 敵 1 匹ぶんの操縦。シミュレーションの乱数だけを使い、リプレイで同じ動きになるようにする
+pub trait SnakeBrain: fmt::Debug + Send + Sync {
+    fn choose(&mut self, me: &SimSnake, world: &WorldView, rng: &mut GameRng) -> Direction;
+}
+
+/// 体の長さより長く直進したら、重み付きの乱数と餌の重心への引力で曲がる
+#[derive(Debug)]
+pub struct WeightedWalker {
+    // [上, 右, 下, 左] を今の向き基準で
+    direction_weight: [i32; 4],
+    // 最後に曲がってから進んだ長さ
+    straight: usize,
+}
+
+impl WeightedWalker {
+    pub fn new(direction_weight: [i32; 4]) -> Self {
+        WeightedWalker {
+            direction_weight,
+            straight: 0,
+        }
+    }
+}
+
+impl SnakeBrain for WeightedWalker {
+    fn choose(&mut self, me: &SimSnake, world: &WorldView, rng: &mut GameRng) -> Direction {
+        let mut direction = me.direction();
+        if self.straight > me.len() {
+            let diff = world.food_center - me.head();
+            let food_weight = [
+                if diff.y() > 0 { diff.y() } else { 0 },
+                if diff.x() > 0 { diff.x() } else { 0 },
+                if diff.y() < 0 { -diff.y() } else { 0 },
+                if diff.x() < 0 { -diff.x() } else { 0 },
+            ];
+            // 自分が向いている方向
+            let dir = direction.get_num();
+            // (今の向きを基準として)次に向く方向の確率
+            let dist = WeightedIndex::new(
+                self.direction_weight
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &w)| {
+                        if w == 0 {
+                            0
+                        } else {
+                            food_weight[(i + 4 - dir as usize) % 4] + w
+                        }
+                    })
+                    .collect::<Vec<i32>>(),
+            )
+            .unwrap();
+            direction = Direction::num2dir(dir + dist.sample(rng) as u8);
+            self.straight = 0;
+        }
+        self.straight += 1;
+        direction
+    }
+}
+
+/// 一番近い餌へ、ぶつからない範囲で距離が縮む向きに進む
+#[derive(Debug)]
+pub struct FoodChaser;
+
+impl SnakeBrain for FoodChaser {
+    fn choose(&mut self, me: &SimSnake, world: &WorldView, _rng: &mut GameRng) -> Direction {
+        let target = world.nearest_food(me.head()).unwrap_or(world.food_center);
+        step_toward(me, world, target)
+    }
+}
+
+/// 基本は直進し、前が詰まりそうなら空いている側へ曲がる。たまに気まぐれに曲がる
+#[derive(Debug)]
+pub struct WallAvoider;
+
+impl WallAvoider {
+    // この距離以内に壁や体があれば曲がる
+    const LOOKAHEAD: i32 = 3;
+    // 曲がる側を比べるときに見る距離
+    const HORIZON: i32 = 16;
+}
+
+impl SnakeBrain for WallAvoider {
+    fn choose(&mut self, me: &SimSnake, world: &WorldView, rng: &mut GameRng) -> Direction {
+        let head = me.head();
+        let [forward, right, left] = forward_turns(me.direction());
+        let wander = rng.gen_ratio(1, 12);
+        if world.free_run(head, forward, Self::LOOKAHEAD) == Self::LOOKAHEAD && !wander {
+            return forward;
+        }
+        let runs = [right, left].map(|dir| world.free_run(head, dir, Self::HORIZON));
+        if runs[0] == 0 && runs[1] == 0 {
+            return forward;
+        }
+        if runs[0] == runs[1] {
+            return if rng.gen_bool(0.5) { right } else { left };
+        }
+        if runs[0] > runs[1] {
+            right
+        } else {
+            left
+        }
+    }
+}
+
+/// 一番近くにたどり着ける餌までの道を探してその通りに進む。
+/// 道が塞がるか餌がなくなったら探し直し、予算が足りない tick は FoodChaser と同じく進む
+#[derive(Debug, Default)]
+pub struct PathFinder {
+    target: Option<Position>,
+    // 末尾が次の一歩
+    path: Vec<Direction>,
+}
+
+impl SnakeBrain for PathFinder {
+    fn choose(&mut self, me: &SimSnake, world: &WorldView, rng: &mut GameRng) -> Direction {
+        let head = me.head();
+        let still_valid = self
+            .target
+            .is_some_and(|target| world.foods.iter().any(|food| food.pos() == target))
+            && self
+                .path
+                .last()
+                .is_some_and(|dir| !world.is_blocked(head + dir.to_pos(1)));
+        if !still_valid {
+            (self.target, self.path) = world
+                .find_food(head)
+                .map_or((None, Vec::new()), |(target, path)| (Some(target), path));
+        }
+        match self.path.pop() {
+            Some(dir) => dir,
+            None => FoodChaser.choose(me, world, rng),
+        }
+    }
+}
+
+/// プレイヤーの頭が数 tick 後に来る場所へ先回りし、体で行く手をふさぐ。
+/// 追わない tick は WallAvoider と同じく動く
+#[derive(Debug)]
+pub struct Hunter {
+    // SimConfig::hunter_aggression
+    aggression: u32,
+}
+
+impl Hunter {
+    // 先読みする tick 数の範囲。離れているほど先を狙う
+    const MIN_LEAD: i32 = 2;
+    const MAX_LEAD: i32 = 8;
+
+    pub fn new(aggression: u32) -> Self {
+        Hunter {
+            aggression: aggression.min(100),
+        }
+    }
+}
+
+impl SnakeBrain for Hunter {
+    fn choose(&mut self, me: &SimSnake, world: &WorldView, rng: &mut GameRng) -> Direction {
+        let chase = rng.gen_ratio(self.aggression, 100);
+        let Some(player) = world.player().filter(|_| chase) else {
+            return WallAvoider.choose(me, world, rng);
+        };
+        let lead = (distance(me.head(), player.head()) / 2).clamp(Self::MIN_LEAD, Self::MAX_LEAD);
+        let target = player.head() + player.direction().to_pos(lead);
+        step_toward(me, world, target)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BrainKind {
+    Walker,
+    Chaser,
+    Avoider,
+    PathFinder,
+    Hunter,
+}
+
+impl BrainKind {
+    pub const ALL: [BrainKind; 5] = [
+        BrainKind::Walker,
+        BrainKind::Chaser,
+        BrainKind::Avoider,
+        BrainKind::PathFinder,
+        BrainKind::Hunter,
+    ];
+
+    pub fn build(self, config: &SimConfig) -> Box<dyn SnakeBrain> {
+        match self {
+            BrainKind::Walker => Box::new(WeightedWalker::new(config.direction_weight)),
+            BrainKind::Chaser => Box::new(FoodChaser),
+            BrainKind::Avoider => Box::new(WallAvoider),
+            BrainKind::PathFinder => Box::new(PathFinder::default()),
+            BrainKind::Hunter => Box::new(Hunter::new(config.hunter_aggression)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enemy(id: u32, body: &[(i32, i32)]) -> SimSnake {
+        let body = body.iter().map(|&(x, y)| Position::new(x, y)).collect();
+        SimSnake::enemy(id, Direction::UP, body)
+    }
+
+    fn food(x: i32, y: i32) -> SimFood {
+        SimFood::at(0, Position::new(x, y))
+    }
+
+    // 10x10 のアリーナ。敵は x, y とも -5..15 を動ける
+    fn view<'a>(snakes: &'a [SimSnake], foods: &'a [SimFood], budget: usize) -> WorldView<'a> {
+        let arena = Arena::new(10, 10).unwrap();
+        WorldView::new(arena, snakes, foods, Position::new(5, 5), budget)
+    }
+
+    // path は末尾が最初の一歩なので、逆にたどって通るマスを返す
+    fn walk(from: Position, path: &[Direction]) -> Vec<Position> {
+        path.iter()
+            .rev()
+            .scan(from, |pos, dir| {
+                *pos += dir.to_pos(1);
+                Some(*pos)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn path_to_food_is_shortest() {
+        let snakes = [enemy(0, &[(0, 0), (0, -1)])];
+        let foods = [food(3, 4)];
+        let world = view(&snakes, &foods, 8192);
+        let (target, path) = world.find_food(Position::new(0, 0)).unwrap();
+        assert_eq!(target, Position::new(3, 4));
+        assert_eq!(path.len(), 7);
+        assert_eq!(walk(Position::new(0, 0), &path).last(), Some(&target));
+    }
+
+    #[test]
+    fn path_goes_around_bodies_inside_the_enemy_arena() {
+        // x = 2 の壁は y = 13, 14 だけ空いている
+        let wall = (-5..=12).rev().map(|y| (2, y)).collect::<Vec<_>>();
+        let snakes = [enemy(0, &[(0, 0), (0, -1)]), enemy(1, &wall)];
+        let foods = [food(4, 0)];
+        let world = view(&snakes, &foods, 8192);
+        let (_, path) = world.find_food(Position::new(0, 0)).unwrap();
+        assert_eq!(path.len(), 13 + 4 + 13);
+        for pos in walk(Position::new(0, 0), &path) {
+            assert!(!world.is_blocked(pos), "{pos:?} is blocked");
+        }
+    }
+
+    #[test]
+    fn exhausted_budget_falls_back_without_a_path() {
+        let snakes = [enemy(0, &[(0, 0), (0, -1)])];
+        let foods = [food(3, 4)];
+        let world = view(&snakes, &foods, 1);
+        assert!(world.find_food(Position::new(0, 0)).is_none());
+        let mut brain = PathFinder::default();
+        let dir = brain.choose(&snakes[0], &world, &mut GameRng::new(0));
+        assert_eq!(
+            dir,
+            FoodChaser.choose(&snakes[0], &world, &mut GameRng::new(0))
+        );
+    }
+
+    #[test]
+    fn a_failed_search_does_not_starve_later_snakes() {
+        let snakes = [enemy(0, &[(-4, 14), (-4, 13)]), enemy(1, &[(3, 0), (2, 0)])];
+        let foods = [food(4, 0)];
+        let world = view(&snakes, &foods, 40);
+        assert!(world.find_food(Position::new(-4, 14)).is_none());
+        assert!(world.find_food(Position::new(3, 0)).is_some());
+    }
+}
@@ -2,6 +2,8 @@ use std::ops::{Add, AddAssign, Div, Mul, MulAssign, Sub, SubAssign};
 
 use bevy::prelude::*;
 
+use crate::simulation::{FoodId, SnakeId};
+
 #[derive(Component, Clone, Copy, PartialEq, Eq, Default, Debug)]
 pub struct Position {
     x: i32,
@@ -149,41 +151,22 @@ impl Direction {
 
 #[derive(Component)]
 pub struct Snake {
-    direction: Direction,
+    id: SnakeId,
     bodies: Vec<Entity>,
-    pub tail_pos: Position,
 }
-impl Default for Snake {
-    fn default() -> Self {
+impl Snake {
+    pub fn new(id: SnakeId) -> Self {
         Snake {
-            direction: Direction::UP,
+            id,
             bodies: Vec::new(),
-            tail_pos: Position::default(),
         }
     }
-}
-impl Snake {
+    pub fn id(&self) -> SnakeId {
+        self.id
+    }
     pub fn add(&mut self, body: Entity) {
         self.bodies.push(body);
     }
-    pub fn get_dir(&self) -> Direction {
-        self.direction
-    }
-    pub fn set_dir(&mut self, dir: Direction) {
-        self.direction = dir;
-    }
-    pub fn get_head(&self) -> &Entity {
-        self.bodies.first().unwrap()
-    }
-    pub fn get_tail(&self) -> &Entity {
-        self.bodies.last().unwrap()
-    }
-    pub fn set_tail_pos(&mut self, pos: Position) {
-        self.tail_pos = pos;
-    }
-    pub fn get_tail_pos(&self) -> Position {
-        self.tail_pos
-    }
     pub fn despawn(&self, commands: &mut Commands) {
         for ent in self.bodies.iter() {
             commands.entity(*ent).despawn();
@@ -192,9 +175,6 @@ impl Snake {
     pub fn iter(&self) -> impl Iterator<Item = &Entity> {
         self.bodies.iter()
     }
-    // pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Entity> {
-    //     self.bodies.iter_mut()
-    // }
     pub fn len(&self) -> usize {
         self.bodies.len()
     }
@@ -203,23 +183,29 @@ impl Snake {
 #[derive(Component)]
 pub struct Player;
 
-#[derive(Component, Default)]
-pub struct Enemy(usize);
-
-impl Enemy {
-    pub fn get(&self) -> usize {
-        self.0
-    }
-    pub fn increment(&mut self) {
-        self.0 += 1;
-    }
-    pub fn reset(&mut self) {
-        self.0 = 0;
-    }
-}
+#[derive(Component)]
+pub struct Enemy;
 
 #[derive(Component)]
-pub struct Food;
+pub struct Food(pub FoodId);
 
 #[derive(Component)]
 pub struct FlashMask;
+
+// メニューで選んでいる難易度を出すテキスト
+#[derive(Component)]
+pub struct DifficultyText;
+
+// スコア画面で入力中の正解を出すテキスト
+#[derive(Component)]
+pub struct LabelText;
+
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ButtonAction {
+    Play,
+    // 直前のゲームをもう一度再生する
+    Replay,
+    Menu,
+    // GameSettings::difficulties の添字
+    Difficulty(usize),
+}
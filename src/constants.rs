@@ -16,23 +16,42 @@ pub const FOOD_SIZE: f32 = 0.8;
 
 pub const ARENA_WIDTH: u32 = 56;
 pub const ARENA_HEIGHT: u32 = 28;
+pub const ARENA_MIN: u32 = 8;
+// 敵の範囲はこの 2 倍四方になり、探索で毎 tick 確保する
+pub const ARENA_MAX: u32 = 1024;
+
+// モデルの入力サイズ
+pub const DIGIT_SIZE: usize = 28;
+// 学習済みモデルの置き場所
+pub const ARTIFACT_DIR: &str = "data";
+// スコア画面で 1 セルを何 px で描くか
+pub const HEATMAP_SCALE: f32 = 6.;
+
+pub const SETTINGS_PATH: &str = "settings.toml";
 
 pub const SNAKE_SPEED: u64 = 10;
 
+pub const FOOD_INTERVAL_TICKS: u64 = 16;
+
+pub const ENEMY_MAX: usize = 30;
+
 // [上, 右, 下, 左]
 pub const DIRECTION_WEIGHT: [i32; 4] = [60, 20, 0, 20];
 
+// 敵が生まれるときに選ぶ操縦の重み [walker, chaser, avoider, pathfinder, hunter]
+pub const BRAIN_WEIGHT: [u32; 5] = [5, 2, 2, 1, 1];
+
+// SimConfig::hunter_aggression の既定値
+pub const HUNTER_AGGRESSION: u32 = 50;
+
+// 経路探索で 1 tick に全員で展開してよいマスの数。敵ごとに等しく分ける
+pub const PATH_BUDGET: usize = 8192;
+
+pub const LEADERBOARD_SIZE: usize = 10;
+
+pub const TEXT_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
+// 今のゲームの順位など、目立たせる文字
+pub const HIGHLIGHT_TEXT_COLOR: Color = Color::srgb(1.0, 0.85, 0.3);
 pub const NORMAL_BUTTON: Color = Color::srgb(0.15, 0.15, 0.15);
 pub const HOVERED_BUTTON: Color = Color::srgb(0.25, 0.25, 0.25);
 pub const PRESSED_BUTTON: Color = Color::srgb(0.35, 0.75, 0.35);
-
-pub fn in_arena(x: i32, y: i32) -> bool {
-    0 <= x && x < ARENA_WIDTH as i32 && 0 <= y && y < ARENA_HEIGHT as i32
-}
-
-pub fn in_enemy_arena(x: i32, y: i32) -> bool {
-    -(ARENA_WIDTH as i32 / 2) <= x
-        && x < 3 * ARENA_WIDTH as i32 / 2
-        && -(ARENA_HEIGHT as i32 / 2) <= y
-        && y < 3 * ARENA_HEIGHT as i32 / 2
-}
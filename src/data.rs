@@ -1,13 +1,37 @@
 use burn::{
-    data::{dataloader::batcher::Batcher, dataset::vision::MnistItem},
+    data::{
+        dataloader::batcher::Batcher,
+        dataset::{
+            vision::{MnistDataset, MnistItem},
+            Dataset,
+        },
+    },
     prelude::*,
 };
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::constants::DIGIT_SIZE;
+use crate::drawings::Drawing;
+use crate::preprocess::{Preprocess, MNIST_MEAN, MNIST_STD};
+use crate::strokes;
+
+// 0-255 の画像を学習時と同じ分布にそろえる
+pub fn normalize<B: Backend>(images: Tensor<B, 3>) -> Tensor<B, 3> {
+    ((images / 255) - MNIST_MEAN) / MNIST_STD
+}
 
 #[derive(Clone)]
 pub struct MnistBatcher<B: Backend> {
     device: B::Device,
 }
 
+impl<B: Backend> MnistBatcher<B> {
+    pub fn new(device: B::Device) -> Self {
+        Self { device }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct MnistBatch<B: Backend> {
     pub images: Tensor<B, 3>,
@@ -21,7 +45,7 @@ impl<B: Backend> Batcher<MnistItem, MnistBatch<B>> for MnistBatcher<B> {
             .map(|item| TensorData::from(item.image).convert::<B::FloatElem>())
             .map(|data| Tensor::<B, 2>::from_data(data, &self.device))
             .map(|tensor| tensor.reshape([1, 28, 28]))
-            .map(|tensor| ((tensor / 255) - 0.1307) / 0.3081)
+            .map(normalize)
             .collect();
 
         let targets = items
@@ -40,3 +64,151 @@ impl<B: Backend> Batcher<MnistItem, MnistBatch<B>> for MnistBatcher<B> {
         MnistBatch { images, targets }
     }
 }
+
+/// ヘビの軌跡で描いた数字 (値は訪問回数)
+#[derive(Clone, Debug)]
+pub struct StrokeItem {
+    pub image: [[f32; DIGIT_SIZE]; DIGIT_SIZE],
+    pub label: u8,
+}
+
+impl StrokeItem {
+    pub fn to_mnist(&self, preprocess: &Preprocess) -> MnistItem {
+        MnistItem {
+            image: preprocess.apply(&self.image),
+            label: self.label,
+        }
+    }
+}
+
+/// 要素ごとにシードから描き直すので、データを持たない
+pub struct StrokeDataset {
+    seed: u64,
+    len: usize,
+}
+
+impl StrokeDataset {
+    pub fn new(seed: u64, len: usize) -> Self {
+        StrokeDataset { seed, len }
+    }
+}
+
+impl Dataset<StrokeItem> for StrokeDataset {
+    fn get(&self, index: usize) -> Option<StrokeItem> {
+        if index >= self.len {
+            return None;
+        }
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        rng.set_stream(index as u64);
+        let label = (index % 10) as u8;
+        Some(StrokeItem {
+            image: strokes::draw(label, &mut rng),
+            label,
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+#[derive(Clone)]
+pub struct StrokeBatcher<B: Backend> {
+    mnist: MnistBatcher<B>,
+    preprocess: Preprocess,
+}
+
+impl<B: Backend> StrokeBatcher<B> {
+    pub fn new(device: B::Device, preprocess: Preprocess) -> Self {
+        Self {
+            mnist: MnistBatcher::new(device),
+            preprocess,
+        }
+    }
+}
+
+impl<B: Backend> Batcher<StrokeItem, MnistBatch<B>> for StrokeBatcher<B> {
+    fn batch(&self, items: Vec<StrokeItem>) -> MnistBatch<B> {
+        self.mnist.batch(
+            items
+                .iter()
+                .map(|item| item.to_mnist(&self.preprocess))
+                .collect(),
+        )
+    }
+}
+
+/// `play` のスコア画面で正解を付けて集めた軌跡
+pub struct DrawingDataset {
+    items: Vec<StrokeItem>,
+}
+
+impl DrawingDataset {
+    pub fn new(drawings: Vec<Drawing>) -> Self {
+        DrawingDataset {
+            items: drawings
+                .into_iter()
+                .map(|drawing| StrokeItem {
+                    image: drawing.grid,
+                    label: drawing.label,
+                })
+                .collect(),
+        }
+    }
+
+    // every 件ごとに 1 件を検証用に回す
+    pub fn split(self, every: usize) -> (Self, Self) {
+        let (valid, train) = self
+            .items
+            .into_iter()
+            .enumerate()
+            .partition::<Vec<_>, _>(|(i, _)| i % every == every - 1);
+        let items = |items: Vec<(usize, StrokeItem)>| DrawingDataset {
+            items: items.into_iter().map(|(_, item)| item).collect(),
+        };
+        (items(train), items(valid))
+    }
+}
+
+impl Dataset<StrokeItem> for DrawingDataset {
+    fn get(&self, index: usize) -> Option<StrokeItem> {
+        self.items.get(index).cloned()
+    }
+
+    fn len(&self) -> usize {
+        self.items.len()
+    }
+}
+
+/// MNIST の後ろに合成データを続けたもの
+pub struct MixedDataset {
+    mnist: MnistDataset,
+    strokes: StrokeDataset,
+    preprocess: Preprocess,
+}
+
+impl MixedDataset {
+    pub fn new(mnist: MnistDataset, strokes: StrokeDataset, preprocess: Preprocess) -> Self {
+        MixedDataset {
+            mnist,
+            strokes,
+            preprocess,
+        }
+    }
+}
+
+impl Dataset<MnistItem> for MixedDataset {
+    fn get(&self, index: usize) -> Option<MnistItem> {
+        match index.checked_sub(self.mnist.len()) {
+            None => self.mnist.get(index),
+            Some(index) => self
+                .strokes
+                .get(index)
+                .map(|item| item.to_mnist(&self.preprocess)),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.mnist.len() + self.strokes.len()
+    }
+}
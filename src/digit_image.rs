@@ -0,0 +1,131 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use image::{imageops::FilterType, ImageError};
+
+use crate::constants::DIGIT_SIZE;
+use crate::resources::PlayerScore;
+
+#[derive(Debug)]
+pub enum DigitImageError {
+    Io(io::Error),
+    Image(ImageError),
+    Parse(String),
+}
+
+impl fmt::Display for DigitImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "failed to read image: {err}"),
+            Self::Image(err) => write!(f, "failed to decode image: {err}"),
+            Self::Parse(reason) => write!(f, "invalid grid: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for DigitImageError {}
+
+impl From<io::Error> for DigitImageError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<ImageError> for DigitImageError {
+    fn from(err: ImageError) -> Self {
+        Self::Image(err)
+    }
+}
+
+/// PNG か数値の並んだテキストを読み、画像の上の行から順に並べたグリッドにする
+pub fn load(path: &Path) -> Result<[[f32; DIGIT_SIZE]; DIGIT_SIZE], DigitImageError> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("png") => load_png(path),
+        _ => parse_raw(&fs::read_to_string(path)?),
+    }
+}
+
+// 輝度を 0-255 のまま使う。大きさが違えば縮小する
+fn load_png(path: &Path) -> Result<[[f32; DIGIT_SIZE]; DIGIT_SIZE], DigitImageError> {
+    let mut image = image::open(path)?.into_luma8();
+    let size = DIGIT_SIZE as u32;
+    if image.dimensions() != (size, size) {
+        image = image::imageops::resize(&image, size, size, FilterType::Triangle);
+    }
+
+    let mut grid = [[0.; DIGIT_SIZE]; DIGIT_SIZE];
+    for (x, y, pixel) in image.enumerate_pixels() {
+        grid[y as usize][x as usize] = pixel.0[0] as f32;
+    }
+    Ok(grid)
+}
+
+/// ゲーム終了時の軌跡を `{stem}-digit-0.png`, `{stem}-digit-1.png` (モデルに渡す 28x28)
+/// と `{stem}-trail.png` (アリーナ全体を 1 セル 1 画素) として dir に書き出す
+pub fn export(
+    dir: &Path,
+    stem: &str,
+    score: &PlayerScore,
+) -> Result<Vec<PathBuf>, DigitImageError> {
+    fs::create_dir_all(dir)?;
+    let mut paths = Vec::new();
+    for i in 0..2 {
+        let path = dir.join(format!("{stem}-digit-{i}.png"));
+        save_rows(&path, &score.digit_grid(i))?;
+        paths.push(path);
+    }
+    let path = dir.join(format!("{stem}-trail.png"));
+    save_rows(&path, &score.trail())?;
+    paths.push(path);
+    Ok(paths)
+}
+
+/// 最大値が 255 になるよう伸ばした輝度を、上の行から順に並べる。
+/// 訪問回数のままでも前処理後の画素でもよい
+pub fn grayscale<R: AsRef<[f32]>>(rows: &[R]) -> Vec<u8> {
+    let max = rows
+        .iter()
+        .flat_map(|row| row.as_ref().iter().copied())
+        .fold(0., f32::max);
+    rows.iter()
+        .flat_map(|row| row.as_ref().iter())
+        .map(|&v| if max > 0. { (v / max * 255.) as u8 } else { 0 })
+        .collect()
+}
+
+/// 行を上から順に 8 bit グレースケールの PNG にする
+pub fn save_rows<R: AsRef<[f32]>>(path: &Path, rows: &[R]) -> Result<(), DigitImageError> {
+    let width = rows.first().map_or(0, |row| row.as_ref().len()) as u32;
+    let image = image::GrayImage::from_raw(width, rows.len() as u32, grayscale(rows))
+        .expect("rows should all have the same length");
+    image.save(path)?;
+    Ok(())
+}
+
+// 空白かカンマ区切りで DIGIT_SIZE * DIGIT_SIZE 個の数
+fn parse_raw(text: &str) -> Result<[[f32; DIGIT_SIZE]; DIGIT_SIZE], DigitImageError> {
+    let values = text
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            value
+                .parse::<f32>()
+                .map_err(|_| DigitImageError::Parse(format!("`{value}` is not a number")))
+        })
+        .collect::<Result<Vec<f32>, _>>()?;
+    if values.len() != DIGIT_SIZE * DIGIT_SIZE {
+        return Err(DigitImageError::Parse(format!(
+            "expected {} values, found {}",
+            DIGIT_SIZE * DIGIT_SIZE,
+            values.len()
+        )));
+    }
+
+    let mut grid = [[0.; DIGIT_SIZE]; DIGIT_SIZE];
+    for (i, value) in values.into_iter().enumerate() {
+        grid[i / DIGIT_SIZE][i % DIGIT_SIZE] = value;
+    }
+    Ok(grid)
+}
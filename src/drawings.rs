@@ -0,0 +1,81 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::Resource;
+use serde::{Deserialize, Serialize};
+
+use crate::constants::DIGIT_SIZE;
+
+/// プレイヤーが描いた数字と、本人が申告した正解の組
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Drawing {
+    // `PlayerScore::digit_grid` のまま (前処理前の訪問回数)
+    pub grid: [[f32; DIGIT_SIZE]; DIGIT_SIZE],
+    pub label: u8,
+    pub seed: u64,
+    // UNIX 時間 (秒)
+    pub timestamp: u64,
+}
+
+/// 1 行に 1 件の JSON を追記していくファイル
+#[derive(Resource, Default, Debug)]
+pub struct DrawingLog {
+    path: Option<PathBuf>,
+}
+
+impl DrawingLog {
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("snake-game").join("drawings.jsonl"))
+    }
+
+    pub fn new(path: Option<PathBuf>) -> Self {
+        DrawingLog { path }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn append(&self, drawings: &[Drawing]) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        // 途中で落ちても前の行は壊れないよう、まとめて 1 回で書く
+        let mut text = String::new();
+        for drawing in drawings {
+            text += &serde_json::to_string(drawing)?;
+            text.push('\n');
+        }
+        file.write_all(text.as_bytes())
+    }
+}
+
+/// 書きかけの最終行など、読めない行は飛ばして件数を知らせる
+pub fn load(path: &Path) -> io::Result<Vec<Drawing>> {
+    let mut drawings = Vec::new();
+    let mut skipped = 0;
+    for line in BufReader::new(fs::File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Drawing>(&line) {
+            Ok(drawing) if drawing.label < 10 => drawings.push(drawing),
+            _ => skipped += 1,
+        }
+    }
+    if skipped > 0 {
+        eprintln!(
+            "skipped {skipped} unreadable drawings in {}",
+            path.display()
+        );
+    }
+    Ok(drawings)
+}
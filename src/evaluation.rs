@@ -0,0 +1,185 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use burn::data::dataset::{vision::MnistDataset, Dataset};
+use serde::Serialize;
+
+use crate::drawings;
+use crate::inference::{DigitRecognizer, ModelError};
+
+/// 正解 (行) と予測 (列) の組を数えた表
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(transparent)]
+pub struct Confusion {
+    matrix: [[u32; 10]; 10],
+}
+
+impl Confusion {
+    pub fn add(&mut self, label: u8, predicted: u8) {
+        self.matrix[label as usize][predicted as usize] += 1;
+    }
+    pub fn total(&self) -> u32 {
+        self.matrix.iter().flatten().sum()
+    }
+    pub fn accuracy(&self) -> f32 {
+        let correct = (0..10).map(|i| self.matrix[i][i]).sum::<u32>();
+        ratio(correct, self.total())
+    }
+    // そう予測したもののうち当たっていた割合
+    pub fn precision(&self, digit: usize) -> f32 {
+        let predicted = self.matrix.iter().map(|row| row[digit]).sum();
+        ratio(self.matrix[digit][digit], predicted)
+    }
+    // その数字のうち当てられた割合
+    pub fn recall(&self, digit: usize) -> f32 {
+        ratio(self.matrix[digit][digit], self.matrix[digit].iter().sum())
+    }
+}
+
+// 分母が 0 なら 0 とする
+fn ratio(a: u32, b: u32) -> f32 {
+    if b == 0 {
+        0.
+    } else {
+        a as f32 / b as f32
+    }
+}
+
+impl fmt::Display for Confusion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "accuracy {:.2}% ({} samples)",
+            self.accuracy() * 100.,
+            self.total()
+        )?;
+        writeln!(f, "digit  precision   recall")?;
+        for digit in 0..10 {
+            writeln!(
+                f,
+                "{digit:>5}  {:>8.2}%  {:>6.2}%",
+                self.precision(digit) * 100.,
+                self.recall(digit) * 100.
+            )?;
+        }
+        writeln!(f, "confusion (rows: label, columns: predicted)")?;
+        write!(f, "     ")?;
+        for digit in 0..10 {
+            write!(f, "{digit:>6}")?;
+        }
+        for (label, row) in self.matrix.iter().enumerate() {
+            write!(f, "\n{label:>5}")?;
+            for count in row {
+                write!(f, "{count:>6}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub dataset: String,
+    pub samples: u32,
+    pub accuracy: f32,
+    pub precision: Vec<f32>,
+    pub recall: Vec<f32>,
+    pub confusion: Confusion,
+}
+
+impl Report {
+    fn new(dataset: impl Into<String>, confusion: Confusion) -> Self {
+        Report {
+            dataset: dataset.into(),
+            samples: confusion.total(),
+            accuracy: confusion.accuracy(),
+            precision: (0..10).map(|digit| confusion.precision(digit)).collect(),
+            recall: (0..10).map(|digit| confusion.recall(digit)).collect(),
+            confusion,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum EvalError {
+    Model(ModelError),
+    // 集めた軌跡のファイルが読めない
+    Drawings(PathBuf, io::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Model(err) => err.fmt(f),
+            Self::Drawings(path, err) => {
+                write!(f, "failed to read drawings {}: {err}", path.display())
+            }
+            Self::Io(err) => write!(f, "failed to write report: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+impl From<ModelError> for EvalError {
+    fn from(err: ModelError) -> Self {
+        Self::Model(err)
+    }
+}
+
+impl From<io::Error> for EvalError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// MNIST のテストデータと、あれば集めた軌跡でモデルを測る。
+/// 結果は表として出力し、json があればそこにも書く
+pub fn run(
+    artifact_dir: &str,
+    drawings: Option<&Path>,
+    mnist: bool,
+    json: Option<&Path>,
+) -> Result<(), EvalError> {
+    let model = DigitRecognizer::load(artifact_dir)?;
+    let mut reports = Vec::new();
+
+    if mnist {
+        let mut confusion = Confusion::default();
+        for item in MnistDataset::test().iter() {
+            confusion.add(item.label, model.predict_pixels(&item.image).digit());
+        }
+        reports.push(Report::new("mnist-test", confusion));
+    }
+    // 軌跡は学習時と同じ前処理を通す
+    match drawings {
+        Some(path) if path.exists() => {
+            let mut confusion = Confusion::default();
+            let drawings =
+                drawings::load(path).map_err(|err| EvalError::Drawings(path.to_path_buf(), err))?;
+            for drawing in drawings {
+                confusion.add(drawing.label, model.predict(&drawing.grid).digit());
+            }
+            reports.push(Report::new(path.display().to_string(), confusion));
+        }
+        Some(path) => println!("no labelled drawings at {}", path.display()),
+        None => {}
+    }
+
+    for report in reports.iter() {
+        println!("== {}\n{}\n", report.dataset, report.confusion);
+    }
+    if let Some(path) = json {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(
+            path,
+            serde_json::to_string_pretty(&reports).map_err(io::Error::from)?,
+        )?;
+    }
+    Ok(())
+}
@@ -1,24 +1,287 @@
+use std::{fmt, io, path::PathBuf};
+
+#[cfg(feature = "candle")]
+use burn::backend::Candle;
+#[cfg(feature = "ndarray")]
+use burn::backend::NdArray;
+#[cfg(feature = "wgpu")]
+use burn::backend::Wgpu;
 use burn::{
     config::Config,
     module::Module,
     prelude::Backend,
     record::{CompactRecorder, Recorder},
-    tensor::{cast::ToElement, Tensor},
+    tensor::{activation::softmax, Tensor, TensorData},
 };
 
-use crate::training::TrainingConfig;
+use crate::constants::DIGIT_SIZE;
+use crate::data::normalize;
+use crate::manifest::{manifest_path, Manifest, ManifestError};
+use crate::model::Model;
+use crate::preprocess::Preprocess;
+use crate::training::{config_path, model_path, TrainingConfig};
+
+#[cfg(not(any(feature = "wgpu", feature = "ndarray", feature = "candle")))]
+compile_error!("enable at least one of the `wgpu`, `ndarray` or `candle` features");
+
+#[derive(Debug)]
+pub enum ModelError {
+    NoBackend,
+    Missing(PathBuf),
+    Config(String),
+    Weights(String),
+    Data(String),
+    Manifest(ManifestError),
+    Io(io::Error),
+    Save(String),
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoBackend => write!(
+                f,
+                "no GPU adapter found and no CPU backend built in (enable the `ndarray` feature)"
+            ),
+            Self::Missing(path) => write!(f, "model file {} not found", path.display()),
+            Self::Config(err) => write!(f, "failed to load model config: {err}"),
+            Self::Weights(err) => write!(f, "failed to load model weights: {err}"),
+            Self::Data(err) => write!(f, "failed to load training data: {err}"),
+            Self::Manifest(err) => write!(f, "incompatible model: {err}"),
+            Self::Io(err) => write!(f, "failed to write model files: {err}"),
+            Self::Save(err) => write!(f, "failed to save trained model: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
+
+impl From<io::Error> for ModelError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// softmax 後の各数字の確率
+#[derive(Clone, Debug, PartialEq)]
+pub struct Prediction {
+    probabilities: Vec<f32>,
+}
+
+impl Prediction {
+    pub fn new(probabilities: Vec<f32>) -> Self {
+        Prediction { probabilities }
+    }
+    pub fn digit(&self) -> u8 {
+        self.top(1).first().map_or(0, |&(digit, _)| digit)
+    }
+    pub fn confidence(&self) -> f32 {
+        self.top(1).first().map_or(0., |&(_, p)| p)
+    }
+    // 確率の高い順に n 個
+    pub fn top(&self, n: usize) -> Vec<(u8, f32)> {
+        let mut candidates = self
+            .probabilities
+            .iter()
+            .enumerate()
+            .map(|(digit, &p)| (digit as u8, p))
+            .collect::<Vec<_>>();
+        candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+        candidates.truncate(n);
+        candidates
+    }
+}
+
+/// 学習済みモデルとそのデバイス。起動時に一度だけ読み込む
+pub struct DigitModel<B: Backend> {
+    model: Model<B>,
+    device: B::Device,
+    preprocess: Preprocess,
+}
+
+impl<B: Backend> DigitModel<B> {
+    pub fn load(artifact_dir: &str, device: B::Device) -> Result<Self, ModelError> {
+        let config_path = config_path(artifact_dir);
+        let record_path = model_path(artifact_dir);
+        for path in [&config_path, &record_path.with_extension("mpk")] {
+            if !path.exists() {
+                return Err(ModelError::Missing(path.clone()));
+            }
+        }
+
+        let config = TrainingConfig::load(&config_path)
+            .map_err(|err| ModelError::Config(err.to_string()))?;
+        // 重みを読む前に、このビルドのモデルと入力の形が合うかを確かめる
+        Manifest::load(&manifest_path(artifact_dir))
+            .and_then(|manifest| manifest.validate(config.model.num_classes()))
+            .map_err(ModelError::Manifest)?;
+        let record = CompactRecorder::new()
+            .load(record_path, &device)
+            .map_err(|err| ModelError::Weights(err.to_string()))?;
+        let model = config.model.init::<B>(&device).load_record(record);
+
+        Ok(DigitModel {
+            model,
+            device,
+            preprocess: config.preprocess,
+        })
+    }
+
+    pub fn infer(&self, item: Tensor<B, 3>) -> Prediction {
+        let output = softmax(self.model.forward(item), 1);
+        let probabilities = output
+            .into_data()
+            .convert::<f32>()
+            .to_vec::<f32>()
+            .expect("Model output should be a float tensor");
 
-pub fn infer<B: Backend>(artifact_dir: &str, device: B::Device, item: Tensor<B, 3>) -> u8 {
-    let config = TrainingConfig::load(format!("{artifact_dir}/{artifact_dir}.config.json"))
-        .expect("Config should exist for the model");
-    let record = CompactRecorder::new()
-        .load(format!("{artifact_dir}/model").into(), &device)
-        .expect("Trained model should exist");
+        Prediction::new(probabilities)
+    }
 
-    let model = config.model.init::<B>(&device).load_record(record);
+    pub fn preprocess(&self) -> Preprocess {
+        self.preprocess
+    }
 
-    let output = model.forward(item);
-    let predicted = output.argmax(1).flatten::<1>(0, 1).into_scalar();
+    // 0-255 の画像をそのまま読む
+    pub fn predict_pixels(&self, image: &[[f32; DIGIT_SIZE]; DIGIT_SIZE]) -> Prediction {
+        let data = image.iter().flatten().copied().collect::<Vec<f32>>();
+        let tensor_data = TensorData::new(data, [1, DIGIT_SIZE, DIGIT_SIZE]);
+        self.infer(normalize(Tensor::from_data(tensor_data, &self.device)))
+    }
+
+    // ヘビの軌跡 (訪問回数) を学習時と同じ前処理に通して読む
+    pub fn predict(&self, grid: &[[f32; DIGIT_SIZE]; DIGIT_SIZE]) -> Prediction {
+        self.predict_pixels(&self.preprocess.apply(grid))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BackendKind {
+    #[cfg(feature = "wgpu")]
+    Wgpu,
+    #[cfg(feature = "ndarray")]
+    NdArray,
+    #[cfg(feature = "candle")]
+    Candle,
+}
+
+// 優先順。使えないものは飛ばす
+const BACKENDS: &[BackendKind] = &[
+    #[cfg(feature = "wgpu")]
+    BackendKind::Wgpu,
+    #[cfg(feature = "ndarray")]
+    BackendKind::NdArray,
+    #[cfg(feature = "candle")]
+    BackendKind::Candle,
+];
+
+impl BackendKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "wgpu")]
+            Self::Wgpu => "wgpu",
+            #[cfg(feature = "ndarray")]
+            Self::NdArray => "ndarray",
+            #[cfg(feature = "candle")]
+            Self::Candle => "candle",
+        }
+    }
+
+    // 使えるもののうち最も優先度の高いもの
+    pub fn detect() -> Option<Self> {
+        BACKENDS.iter().copied().find(|kind| kind.is_available())
+    }
+
+    fn is_available(self) -> bool {
+        match self {
+            #[cfg(feature = "wgpu")]
+            Self::Wgpu => wgpu_available(),
+            #[allow(unreachable_patterns)]
+            _ => true,
+        }
+    }
+}
+
+// アダプタが見つからないと burn は最初の演算で panic するので、小さな演算で確かめる
+#[cfg(feature = "wgpu")]
+fn wgpu_available() -> bool {
+    std::panic::catch_unwind(|| {
+        let device = Default::default();
+        Tensor::<Wgpu, 1>::zeros([1], &device).into_data();
+    })
+    .is_ok()
+}
+
+/// ビルドに含まれたバックエンドのうち、実行時に使えるもので読み込んだモデル
+pub enum DigitRecognizer {
+    #[cfg(feature = "wgpu")]
+    Wgpu(DigitModel<Wgpu>),
+    #[cfg(feature = "ndarray")]
+    NdArray(DigitModel<NdArray>),
+    #[cfg(feature = "candle")]
+    Candle(DigitModel<Candle>),
+}
+
+impl DigitRecognizer {
+    pub fn load(artifact_dir: &str) -> Result<Self, ModelError> {
+        let kind = BackendKind::detect().ok_or(ModelError::NoBackend)?;
+        let recognizer = match kind {
+            #[cfg(feature = "wgpu")]
+            BackendKind::Wgpu => Self::Wgpu(DigitModel::load(artifact_dir, Default::default())?),
+            #[cfg(feature = "ndarray")]
+            BackendKind::NdArray => {
+                Self::NdArray(DigitModel::load(artifact_dir, Default::default())?)
+            }
+            #[cfg(feature = "candle")]
+            BackendKind::Candle => {
+                Self::Candle(DigitModel::load(artifact_dir, Default::default())?)
+            }
+        };
+        println!("digit model loaded on {}", kind.name());
+        Ok(recognizer)
+    }
+
+    pub fn predict(&self, grid: &[[f32; DIGIT_SIZE]; DIGIT_SIZE]) -> Prediction {
+        match self {
+            #[cfg(feature = "wgpu")]
+            Self::Wgpu(model) => model.predict(grid),
+            #[cfg(feature = "ndarray")]
+            Self::NdArray(model) => model.predict(grid),
+            #[cfg(feature = "candle")]
+            Self::Candle(model) => model.predict(grid),
+        }
+    }
+
+    pub fn preprocess(&self) -> Preprocess {
+        match self {
+            #[cfg(feature = "wgpu")]
+            Self::Wgpu(model) => model.preprocess(),
+            #[cfg(feature = "ndarray")]
+            Self::NdArray(model) => model.preprocess(),
+            #[cfg(feature = "candle")]
+            Self::Candle(model) => model.preprocess(),
+        }
+    }
+
+    pub fn predict_pixels(&self, image: &[[f32; DIGIT_SIZE]; DIGIT_SIZE]) -> Prediction {
+        match self {
+            #[cfg(feature = "wgpu")]
+            Self::Wgpu(model) => model.predict_pixels(image),
+            #[cfg(feature = "ndarray")]
+            Self::NdArray(model) => model.predict_pixels(image),
+            #[cfg(feature = "candle")]
+            Self::Candle(model) => model.predict_pixels(image),
+        }
+    }
+}
 
-    predicted.to_u8()
+/// 重みを読まずに、学習設定にある前処理だけを読む
+pub fn load_preprocess(artifact_dir: &str) -> Result<Preprocess, ModelError> {
+    let path = config_path(artifact_dir);
+    if !path.exists() {
+        return Err(ModelError::Missing(path));
+    }
+    TrainingConfig::load(&path)
+        .map(|config| config.preprocess)
+        .map_err(|err| ModelError::Config(err.to_string()))
 }
@@ -0,0 +1,127 @@
+use std::{fs, io, path::PathBuf, time::Duration};
+
+use bevy::prelude::Resource;
+use serde::{Deserialize, Serialize};
+
+use crate::constants::LEADERBOARD_SIZE;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LeaderboardEntry {
+    pub score: u32,
+    pub food_count: u8,
+    pub digits: [u8; 2],
+    pub length: usize,
+    pub duration_ms: u64,
+    // UNIX 時間 (秒)
+    pub timestamp: u64,
+    pub seed: u64,
+}
+
+impl LeaderboardEntry {
+    pub fn summary(&self) -> String {
+        let duration = Duration::from_millis(self.duration_ms).as_secs();
+        format!(
+            "{:>4}  food {:>3}  digits {} {}  len {:>3}  {}:{:02}  {}  seed {}",
+            self.score,
+            self.food_count,
+            self.digits[0],
+            self.digits[1],
+            self.length,
+            duration / 60,
+            duration % 60,
+            format_date(self.timestamp),
+            self.seed,
+        )
+    }
+}
+
+#[derive(Resource, Default, Debug)]
+pub struct Leaderboard {
+    entries: Vec<LeaderboardEntry>,
+    path: Option<PathBuf>,
+}
+
+impl Leaderboard {
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("snake-game").join("leaderboard.json"))
+    }
+
+    // 読めないファイルは退避して空の表から始める
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let Some(path) = path else {
+            return Self::default();
+        };
+        let entries = match fs::read_to_string(&path) {
+            Ok(text) => match serde_json::from_str::<Vec<LeaderboardEntry>>(&text) {
+                Ok(mut entries) => {
+                    sort(&mut entries);
+                    entries.truncate(LEADERBOARD_SIZE);
+                    entries
+                }
+                Err(err) => {
+                    let backup = path.with_extension("json.corrupt");
+                    eprintln!(
+                        "leaderboard {} is corrupt ({err}), moving it to {}",
+                        path.display(),
+                        backup.display()
+                    );
+                    let _ = fs::rename(&path, &backup);
+                    Vec::new()
+                }
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => {
+                eprintln!("failed to read leaderboard {}: {err}", path.display());
+                Vec::new()
+            }
+        };
+        Leaderboard {
+            entries,
+            path: Some(path),
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // 書き込み途中で落ちても元のファイルが壊れないよう、一時ファイル経由で置き換える
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&self.entries)?)?;
+        fs::rename(&tmp, path)
+    }
+
+    pub fn entries(&self) -> &[LeaderboardEntry] {
+        &self.entries
+    }
+
+    // 表に残れば順位 (0 始まり) を返す
+    pub fn insert(&mut self, entry: LeaderboardEntry) -> Option<usize> {
+        self.entries.push(entry.clone());
+        sort(&mut self.entries);
+        self.entries.truncate(LEADERBOARD_SIZE);
+        self.entries.iter().position(|e| *e == entry)
+    }
+}
+
+// 点数の高い順、同点なら先に出した方が上
+fn sort(entries: &mut [LeaderboardEntry]) {
+    entries.sort_by(|a, b| b.score.cmp(&a.score).then(a.timestamp.cmp(&b.timestamp)));
+}
+
+fn format_date(timestamp: u64) -> String {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = (timestamp / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{year:04}-{month:02}-{day:02}")
+}
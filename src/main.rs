@@ -1,13 +1,443 @@
 mod app;
+mod bench;
+mod brain;
 mod components;
 mod constants;
 mod data;
-mod events;
+mod digit_image;
+mod drawings;
+mod evaluation;
+mod hash;
 mod inference;
+mod leaderboard;
+mod manifest;
 mod model;
+mod preprocess;
+mod replay;
 mod resources;
+mod settings;
+mod simulation;
+mod strokes;
 mod training;
 
-fn main() {
-    app::play();
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::constants::{ARENA_MAX, ARENA_MIN, ARTIFACT_DIR, SETTINGS_PATH};
+use crate::digit_image::DigitImageError;
+use crate::drawings::DrawingLog;
+use crate::inference::{DigitRecognizer, ModelError};
+use crate::leaderboard::Leaderboard;
+use crate::preprocess::Preprocess;
+use crate::replay::Replay;
+use crate::resources::PlayerScore;
+use crate::settings::GameSettings;
+use crate::training::{TrainData, TrainOptions, VALID_STROKE_RATIO};
+
+#[derive(Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    play: PlayArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Play the game (the default when no subcommand is given)
+    Play(PlayArgs),
+    /// Train the digit recognition model on MNIST
+    Train(TrainArgs),
+    /// Classify a PNG image or a raw 28x28 grid with the trained model
+    Infer(InferArgs),
+    /// Measure the trained model on MNIST and the labelled drawings
+    Eval(EvalArgs),
+    /// Play back a recorded game and verify its final score
+    Replay(ReplayArgs),
+    /// Measure simulation and inference speed without opening a window
+    Bench(BenchArgs),
+}
+
+#[derive(Args)]
+struct SettingsArgs {
+    /// Game settings file (defaults to settings.toml if it exists)
+    #[arg(long, value_name = "FILE")]
+    settings: Option<PathBuf>,
+    /// Snake speed; one tick lasts 600 / speed milliseconds. Keeps this speed for the whole
+    /// game, ignoring the speeds set by difficulty stages
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..=600))]
+    speed: Option<u64>,
+}
+
+#[derive(Args)]
+struct GameArgs {
+    /// Seed for every random decision in the game (random per game if omitted)
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Arena width in cells
+    #[arg(long, value_parser = clap::value_parser!(u32).range(ARENA_MIN as i64..=ARENA_MAX as i64))]
+    arena_width: Option<u32>,
+    /// Arena height in cells
+    #[arg(long, value_parser = clap::value_parser!(u32).range(ARENA_MIN as i64..=ARENA_MAX as i64))]
+    arena_height: Option<u32>,
+    /// Name of a difficulty from the settings file
+    #[arg(long, value_name = "NAME")]
+    difficulty: Option<String>,
+}
+
+#[derive(Args)]
+struct PlayArgs {
+    #[command(flatten)]
+    settings: SettingsArgs,
+    #[command(flatten)]
+    game: GameArgs,
+    /// Open the window in borderless fullscreen
+    #[arg(long)]
+    fullscreen: bool,
+    /// Save a replay of every game into this directory
+    #[arg(long, value_name = "DIR")]
+    record: Option<PathBuf>,
+    /// Save the player's trail of every game as PNGs into this directory
+    #[arg(long, value_name = "DIR")]
+    export: Option<PathBuf>,
+    /// File that digits labelled on the score screen are appended to
+    /// (defaults to drawings.jsonl in the user data directory)
+    #[arg(long, value_name = "FILE")]
+    drawings: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct TrainArgs {
+    /// Directory holding the model config, checkpoints and weights
+    #[arg(long, value_name = "DIR", default_value = ARTIFACT_DIR)]
+    artifact_dir: String,
+    /// Total number of epochs to train for
+    #[arg(long)]
+    epochs: Option<usize>,
+    /// Number of images per batch
+    #[arg(long)]
+    batch_size: Option<usize>,
+    /// Learning rate of the Adam optimizer
+    #[arg(long)]
+    learning_rate: Option<f64>,
+    /// Training data
+    #[arg(long, value_enum, default_value_t = TrainData::Mnist)]
+    data: TrainData,
+    /// Number of synthetic snake-stroke digits for `--data mixed` or `--data strokes`
+    /// (at least 6, so that the validation split is not empty)
+    #[arg(
+        long,
+        value_name = "COUNT",
+        default_value_t = 20_000,
+        value_parser = clap::value_parser!(u64).range(VALID_STROKE_RATIO as u64..),
+    )]
+    strokes: u64,
+    /// Labelled drawings for `--data drawings` (defaults to the file `play` appends to)
+    #[arg(long, value_name = "FILE")]
+    drawings: Option<PathBuf>,
+    /// Start from scratch instead of resuming from the latest checkpoint
+    #[arg(long)]
+    fresh: bool,
+}
+
+#[derive(Args)]
+struct InferArgs {
+    /// PNG image (white digit on black) or a text file of 28x28 numbers
+    image: PathBuf,
+    /// Directory holding the trained model
+    #[arg(long, value_name = "DIR", default_value = ARTIFACT_DIR)]
+    artifact_dir: String,
+}
+
+#[derive(Args)]
+struct EvalArgs {
+    /// Directory holding the trained model
+    #[arg(long, value_name = "DIR", default_value = ARTIFACT_DIR)]
+    artifact_dir: String,
+    /// Labelled drawings to evaluate on (defaults to the file `play` appends to)
+    #[arg(long, value_name = "FILE")]
+    drawings: Option<PathBuf>,
+    /// Skip the MNIST test split
+    #[arg(long)]
+    no_mnist: bool,
+    /// Also write the reports as JSON to this file
+    #[arg(long, value_name = "FILE")]
+    json: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct ReplayArgs {
+    /// Replay file written by `play --record`
+    file: PathBuf,
+    /// Settings file for colors and sizes; the game itself runs with the recorded settings
+    /// (defaults to settings.toml if it exists)
+    #[arg(long, value_name = "FILE")]
+    settings: Option<PathBuf>,
+    /// Verify the replay without opening a window
+    #[arg(long)]
+    headless: bool,
+    /// Write the two 28x28 digit images of the final trail as PNGs into DIR
+    #[arg(long, value_name = "DIR", requires = "headless")]
+    dump_digits: Option<PathBuf>,
+    /// Open the window in borderless fullscreen
+    #[arg(long, conflicts_with = "headless")]
+    fullscreen: bool,
+}
+
+#[derive(Args)]
+struct BenchArgs {
+    #[command(flatten)]
+    settings: SettingsArgs,
+    #[command(flatten)]
+    game: GameArgs,
+    /// Number of games to simulate
+    #[arg(long, default_value_t = 100)]
+    games: u32,
+    /// Only benchmark the simulation
+    #[arg(long)]
+    no_model: bool,
+}
+
+fn load_settings(
+    args: &SettingsArgs,
+    game: Option<&GameArgs>,
+) -> Result<GameSettings, settings::SettingsError> {
+    let mut settings = match &args.settings {
+        Some(path) => GameSettings::load(path)?,
+        None if Path::new(SETTINGS_PATH).exists() => GameSettings::load(Path::new(SETTINGS_PATH))?,
+        None => GameSettings::default(),
+    };
+    if let Some(speed) = args.speed {
+        settings.speed = speed;
+        settings.clear_stage_speeds();
+    }
+    if let Some(game) = game {
+        if let Some(seed) = game.seed {
+            settings.seed = Some(seed);
+        }
+        if let Some(width) = game.arena_width {
+            settings.arena.width = width;
+        }
+        if let Some(height) = game.arena_height {
+            settings.arena.height = height;
+        }
+        if let Some(difficulty) = &game.difficulty {
+            settings.difficulty = Some(difficulty.clone());
+        }
+    }
+    settings.validate()?;
+    Ok(settings)
+}
+
+fn play(args: PlayArgs) -> ExitCode {
+    let settings = match load_settings(&args.settings, Some(&args.game)) {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    app::play(app::PlayOptions {
+        settings,
+        fullscreen: args.fullscreen,
+        record_dir: args.record,
+        export_dir: args.export,
+        replay: None,
+        leaderboard: Leaderboard::load(Leaderboard::default_path()),
+        drawings: DrawingLog::new(args.drawings.or_else(DrawingLog::default_path)),
+    })
+}
+
+fn train(args: TrainArgs) -> ExitCode {
+    let options = TrainOptions {
+        epochs: args.epochs,
+        batch_size: args.batch_size,
+        learning_rate: args.learning_rate,
+        data: args.data,
+        strokes: args.strokes as usize,
+        drawings: args.drawings.or_else(DrawingLog::default_path),
+        resume: !args.fresh,
+    };
+    match training::run(&args.artifact_dir, &options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn infer(args: InferArgs) -> ExitCode {
+    let grid = match digit_image::load(&args.image) {
+        Ok(grid) => grid,
+        Err(err) => {
+            eprintln!("{}: {err}", args.image.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    let model = match DigitRecognizer::load(&args.artifact_dir) {
+        Ok(model) => model,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let prediction = model.predict_pixels(&grid);
+    println!(
+        "digit {} ({:.1}%)",
+        prediction.digit(),
+        prediction.confidence() * 100.
+    );
+    for (digit, p) in prediction.top(3) {
+        println!("  {digit}  {:>5.1}%", p * 100.);
+    }
+    ExitCode::SUCCESS
+}
+
+fn eval(args: EvalArgs) -> ExitCode {
+    let drawings = args.drawings.or_else(DrawingLog::default_path);
+    match evaluation::run(
+        &args.artifact_dir,
+        drawings.as_deref(),
+        !args.no_mnist,
+        args.json.as_deref(),
+    ) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn replay(args: ReplayArgs) -> ExitCode {
+    let replay = match Replay::load(&args.file) {
+        Ok(replay) => replay,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if args.headless {
+        return match replay.run_headless() {
+            Ok(sim) => {
+                println!(
+                    "replay verified: {} food in {} ticks (seed: {})",
+                    sim.score().food_count(),
+                    sim.tick(),
+                    sim.seed()
+                );
+                if let Some(dir) = &args.dump_digits {
+                    // モデルが実際に見る画像を出すよう、学習時の前処理を使う
+                    let preprocess = match inference::load_preprocess(ARTIFACT_DIR) {
+                        Ok(preprocess) => preprocess,
+                        Err(ModelError::Missing(_)) => {
+                            println!("no trained model; using the default preprocessing");
+                            Preprocess::default()
+                        }
+                        Err(err) => {
+                            eprintln!("{err}");
+                            return ExitCode::FAILURE;
+                        }
+                    };
+                    if let Err(err) = dump_digits(dir, sim.score(), preprocess) {
+                        eprintln!("failed to dump digits: {err}");
+                        return ExitCode::FAILURE;
+                    }
+                }
+                ExitCode::SUCCESS
+            }
+            Err(err) => {
+                eprintln!("{err}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    // 速さも記録された段階に従うので、読むのはファイルだけ
+    let settings_args = SettingsArgs {
+        settings: args.settings,
+        speed: None,
+    };
+    let settings = match load_settings(&settings_args, None) {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    app::play(app::PlayOptions {
+        settings,
+        fullscreen: args.fullscreen,
+        record_dir: None,
+        export_dir: None,
+        replay: Some(replay),
+        leaderboard: Leaderboard::load(Leaderboard::default_path()),
+        drawings: DrawingLog::new(DrawingLog::default_path()),
+    })
+}
+
+// 向きの確認用に、軌跡そのままと前処理後の画像を並べて書き出す
+fn dump_digits(
+    dir: &Path,
+    score: &PlayerScore,
+    preprocess: Preprocess,
+) -> Result<(), DigitImageError> {
+    fs::create_dir_all(dir)?;
+    for i in 0..2 {
+        let grid = score.digit_grid(i);
+        let raw = dir.join(format!("digit-{i}.png"));
+        digit_image::save_rows(&raw, &grid)?;
+        let input = dir.join(format!("digit-{i}-input.png"));
+        digit_image::save_rows(&input, &preprocess.apply(&grid))?;
+        println!("wrote {} and {}", raw.display(), input.display());
+    }
+    Ok(())
+}
+
+fn bench(args: BenchArgs) -> ExitCode {
+    let settings = match load_settings(&args.settings, Some(&args.game)) {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let model = if args.no_model {
+        None
+    } else {
+        match DigitRecognizer::load(ARTIFACT_DIR) {
+            Ok(model) => Some(model),
+            Err(err) => {
+                eprintln!("{err}");
+                return ExitCode::FAILURE;
+            }
+        }
+    };
+
+    let seed = settings.seed.unwrap_or_else(rand::random);
+    bench::run(&settings.sim_config(), seed, args.games, model.as_ref());
+    ExitCode::SUCCESS
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match cli.command {
+        None => play(cli.play),
+        Some(Command::Play(args)) => play(args),
+        Some(Command::Train(args)) => train(args),
+        Some(Command::Infer(args)) => infer(args),
+        Some(Command::Eval(args)) => eval(args),
+        Some(Command::Replay(args)) => replay(args),
+        Some(Command::Bench(args)) => bench(args),
+    }
 }
@@ -0,0 +1,191 @@
+use std::{fmt, fs, io, path::Path, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::constants::DIGIT_SIZE;
+use crate::hash::fnv1a;
+use crate::model::ARCH_VERSION;
+use crate::preprocess::{MNIST_MEAN, MNIST_STD};
+
+const CLASSES: usize = 10;
+
+/// 学習済みモデルと一緒に置き、読み込み時にこのビルドで使えるかを確かめる
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Manifest {
+    pub arch_version: u32,
+    // [高さ, 幅]
+    pub input_size: [usize; 2],
+    pub mean: f32,
+    pub std: f32,
+    pub classes: usize,
+    // 学習に使ったデータの種類と、その中身を表すハッシュ (FNV-1a, 16 進)
+    pub dataset: String,
+    pub dataset_hash: String,
+}
+
+#[derive(Debug)]
+pub enum ManifestError {
+    Missing(PathBuf),
+    Io(io::Error),
+    Parse(serde_json::Error),
+    Version { found: u32, expected: u32 },
+    InputSize([usize; 2]),
+    Normalization { mean: f32, std: f32 },
+    Classes { manifest: usize, config: usize },
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(path) => write!(f, "manifest {} not found", path.display()),
+            Self::Io(err) => write!(f, "failed to read manifest: {err}"),
+            Self::Parse(err) => write!(f, "failed to parse manifest: {err}"),
+            Self::Version { found, expected } => write!(
+                f,
+                "model architecture version {found} is not supported (expected {expected})"
+            ),
+            Self::InputSize([height, width]) => write!(
+                f,
+                "model expects {width}x{height} images, not {DIGIT_SIZE}x{DIGIT_SIZE}"
+            ),
+            Self::Normalization { mean, std } => write!(
+                f,
+                "model was trained with mean {mean} and std {std}, not {MNIST_MEAN} and {MNIST_STD}"
+            ),
+            Self::Classes { manifest, config } => write!(
+                f,
+                "model has {config} classes but the manifest says {manifest} (expected {CLASSES})"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ManifestError {}
+
+impl From<io::Error> for ManifestError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for ManifestError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Parse(err)
+    }
+}
+
+pub fn manifest_path(artifact_dir: &str) -> PathBuf {
+    PathBuf::from(format!("{artifact_dir}/manifest.json"))
+}
+
+impl Manifest {
+    // このビルドで学習したときの内容
+    pub fn new(dataset: impl Into<String>, dataset_hash: u64) -> Self {
+        Manifest {
+            arch_version: ARCH_VERSION,
+            input_size: [DIGIT_SIZE, DIGIT_SIZE],
+            mean: MNIST_MEAN,
+            std: MNIST_STD,
+            classes: CLASSES,
+            dataset: dataset.into(),
+            dataset_hash: format!("{dataset_hash:016x}"),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ManifestError> {
+        if !path.exists() {
+            return Err(ManifestError::Missing(path.to_path_buf()));
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    // config_classes はモデル設定の出力数
+    pub fn validate(&self, config_classes: usize) -> Result<(), ManifestError> {
+        if self.arch_version != ARCH_VERSION {
+            return Err(ManifestError::Version {
+                found: self.arch_version,
+                expected: ARCH_VERSION,
+            });
+        }
+        if self.input_size != [DIGIT_SIZE, DIGIT_SIZE] {
+            return Err(ManifestError::InputSize(self.input_size));
+        }
+        if self.mean != MNIST_MEAN || self.std != MNIST_STD {
+            return Err(ManifestError::Normalization {
+                mean: self.mean,
+                std: self.std,
+            });
+        }
+        if self.classes != CLASSES || config_classes != CLASSES {
+            return Err(ManifestError::Classes {
+                manifest: self.classes,
+                config: config_classes,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// 学習データを表すバイト列をつなげたもののハッシュ
+pub fn dataset_hash<'a>(parts: impl IntoIterator<Item = &'a [u8]>) -> u64 {
+    fnv1a(parts.into_iter().flatten().copied())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_from_this_build_is_accepted() {
+        assert!(Manifest::new("mnist", 0).validate(CLASSES).is_ok());
+    }
+
+    #[test]
+    fn mismatched_manifest_is_rejected() {
+        let manifest = Manifest {
+            arch_version: ARCH_VERSION + 1,
+            ..Manifest::new("mnist", 0)
+        };
+        assert!(matches!(
+            manifest.validate(CLASSES),
+            Err(ManifestError::Version { .. })
+        ));
+
+        let manifest = Manifest {
+            input_size: [DIGIT_SIZE, DIGIT_SIZE + 1],
+            ..Manifest::new("mnist", 0)
+        };
+        assert!(matches!(
+            manifest.validate(CLASSES),
+            Err(ManifestError::InputSize(_))
+        ));
+
+        assert!(matches!(
+            Manifest::new("mnist", 0).validate(CLASSES + 1),
+            Err(ManifestError::Classes { .. })
+        ));
+    }
+
+    #[test]
+    fn missing_manifest_is_rejected() {
+        let path = std::env::temp_dir().join("snake-game-no-such-dir/manifest.json");
+        assert!(matches!(
+            Manifest::load(&path),
+            Err(ManifestError::Missing(missing)) if missing == path
+        ));
+    }
+
+    #[test]
+    fn dataset_hash_is_fnv1a_of_the_joined_parts() {
+        assert_eq!(dataset_hash([]), 0xcbf29ce484222325);
+        assert_eq!(dataset_hash([b"a".as_slice()]), 0xaf63dc4c8601ec8c);
+        assert_eq!(
+            dataset_hash([b"mn".as_slice(), b"ist"]),
+            dataset_hash([b"mnist".as_slice()])
+        );
+    }
+}
@@ -11,6 +11,9 @@ use nn::{
 
 use crate::data::MnistBatch;
 
+// 層の構成や forward を変えたら上げる。古い重みはマニフェストの検証で弾かれる
+pub const ARCH_VERSION: u32 = 1;
+
 #[derive(Module, Debug)]
 pub struct Model<B: Backend> {
     conv1: Conv2d<B>,
@@ -80,6 +83,9 @@ pub struct ModelConfig {
 }
 
 impl ModelConfig {
+    pub fn num_classes(&self) -> usize {
+        self.num_classes
+    }
     pub fn init<B: Backend>(&self, device: &B::Device) -> Model<B> {
         Model {
             conv1: Conv2dConfig::new([1, 8], [3, 3]).init(device),
@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+
+use crate::constants::DIGIT_SIZE;
+
+// MNIST の画素 (0-1) の平均と標準偏差
+pub const MNIST_MEAN: f32 = 0.1307;
+pub const MNIST_STD: f32 = 0.3081;
+
+/// ヘビの軌跡 (訪問回数) を MNIST に近い 0-255 の画像にする手順。
+/// 学習時と推論時で同じものを使うため、モデルの設定と一緒に保存する
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(default)]
+pub struct Preprocess {
+    // 1 セル幅の線を太らせる半径 (0 なら何もしない)
+    pub dilation: usize,
+    // MNIST と同じく重心を中央に寄せる
+    pub center: bool,
+}
+
+impl Default for Preprocess {
+    fn default() -> Self {
+        Preprocess {
+            dilation: 1,
+            center: true,
+        }
+    }
+}
+
+impl Preprocess {
+    pub fn apply(&self, grid: &[[f32; DIGIT_SIZE]; DIGIT_SIZE]) -> [[f32; DIGIT_SIZE]; DIGIT_SIZE] {
+        let mut image = to_pixels(grid);
+        if self.dilation > 0 {
+            image = dilate(&image, self.dilation);
+        }
+        if self.center {
+            image = center(&image);
+        }
+        image
+    }
+}
+
+// 一度でも通ったセルを 255 にする。何度通っても濃さは変えない
+pub fn to_pixels(grid: &[[f32; DIGIT_SIZE]; DIGIT_SIZE]) -> [[f32; DIGIT_SIZE]; DIGIT_SIZE] {
+    grid.map(|row| row.map(|v| v.clamp(0., 1.) * 255.))
+}
+
+// 周囲 radius セルの最大値を取る
+pub fn dilate(
+    image: &[[f32; DIGIT_SIZE]; DIGIT_SIZE],
+    radius: usize,
+) -> [[f32; DIGIT_SIZE]; DIGIT_SIZE] {
+    let range = |i: usize| i.saturating_sub(radius)..(i + radius + 1).min(DIGIT_SIZE);
+    let mut out = [[0.; DIGIT_SIZE]; DIGIT_SIZE];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, cell) in row.iter_mut().enumerate() {
+            *cell = range(i)
+                .flat_map(|a| range(j).map(move |b| image[a][b]))
+                .fold(0., f32::max);
+        }
+    }
+    out
+}
+
+// 重心が中央に来るように平行移動する。はみ出た分は捨てる
+pub fn center(image: &[[f32; DIGIT_SIZE]; DIGIT_SIZE]) -> [[f32; DIGIT_SIZE]; DIGIT_SIZE] {
+    let mut total = 0.;
+    let mut sum = [0.; 2];
+    for (i, row) in image.iter().enumerate() {
+        for (j, &v) in row.iter().enumerate() {
+            total += v;
+            sum[0] += v * i as f32;
+            sum[1] += v * j as f32;
+        }
+    }
+    if total <= 0. {
+        return *image;
+    }
+    let mid = (DIGIT_SIZE - 1) as f32 / 2.;
+    let shift = sum.map(|s| (mid - s / total).round() as isize);
+
+    let mut out = [[0.; DIGIT_SIZE]; DIGIT_SIZE];
+    for (i, row) in image.iter().enumerate() {
+        for (j, &v) in row.iter().enumerate() {
+            let a = i as isize + shift[0];
+            let b = j as isize + shift[1];
+            if (0..DIGIT_SIZE as isize).contains(&a) && (0..DIGIT_SIZE as isize).contains(&b) {
+                out[a as usize][b as usize] = v;
+            }
+        }
+    }
+    out
+}
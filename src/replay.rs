@@ -0,0 +1,401 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::components::Direction;
+use crate::resources::Arena;
+use crate::simulation::{ConfigError, SimConfig, Simulation, Stage, StepInput};
+
+const MAGIC: &[u8; 4] = b"SNKR";
+const VERSION: u8 = 8;
+// 入力なしの tick
+const NO_INPUT: u8 = 4;
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u8),
+    InvalidInput {
+        tick: usize,
+        value: u8,
+    },
+    InvalidConfig(&'static str),
+    Config(ConfigError),
+    Mismatch {
+        recorded_food: u8,
+        replayed_food: u8,
+    },
+    // 記録より早く (または遅く) 終わった
+    InputCountMismatch {
+        recorded: usize,
+        replayed: usize,
+    },
+    // 餌の数は同じだが軌跡が違う
+    DigestMismatch,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "replay i/o error: {err}"),
+            Self::BadMagic => write!(f, "not a replay file"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported replay version {version}")
+            }
+            Self::InvalidInput { tick, value } => {
+                write!(f, "invalid input {value} at tick {tick}")
+            }
+            Self::InvalidConfig(reason) => write!(f, "invalid game config: {reason}"),
+            Self::Config(err) => write!(f, "invalid game config: {err}"),
+            Self::Mismatch {
+                recorded_food,
+                replayed_food,
+            } => write!(
+                f,
+                "replay diverged: recorded {recorded_food} food, replayed {replayed_food}"
+            ),
+            Self::InputCountMismatch { recorded, replayed } => write!(
+                f,
+                "replay diverged: recorded {recorded} ticks, replayed {replayed}"
+            ),
+            Self::DigestMismatch => write!(
+                f,
+                "replay diverged: same food count but the player's trail differs"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<io::Error> for ReplayError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<ConfigError> for ReplayError {
+    fn from(err: ConfigError) -> Self {
+        Self::Config(err)
+    }
+}
+
+/// シード・ゲーム設定・tick ごとのプレイヤー入力と、検証用の最終スコア
+#[derive(Clone, Debug)]
+pub struct Replay {
+    seed: u64,
+    config: SimConfig,
+    inputs: Vec<Option<Direction>>,
+    food_count: u8,
+    digest: u64,
+}
+
+impl Replay {
+    pub fn from_simulation(sim: &Simulation) -> Self {
+        Replay {
+            seed: sim.seed(),
+            config: sim.config().clone(),
+            inputs: sim.inputs().to_vec(),
+            food_count: sim.score().food_count(),
+            digest: sim.score().digest(),
+        }
+    }
+
+    pub fn config(&self) -> &SimConfig {
+        &self.config
+    }
+    pub fn inputs(&self) -> &[Option<Direction>] {
+        &self.inputs
+    }
+
+    pub fn simulation(&self) -> Simulation {
+        Simulation::new(self.config.clone(), self.seed)
+    }
+
+    pub fn verify(&self, sim: &Simulation) -> Result<(), ReplayError> {
+        let score = sim.score();
+        if score.food_count() != self.food_count {
+            return Err(ReplayError::Mismatch {
+                recorded_food: self.food_count,
+                replayed_food: score.food_count(),
+            });
+        }
+        if sim.inputs().len() != self.inputs.len() {
+            return Err(ReplayError::InputCountMismatch {
+                recorded: self.inputs.len(),
+                replayed: sim.inputs().len(),
+            });
+        }
+        if score.digest() != self.digest {
+            return Err(ReplayError::DigestMismatch);
+        }
+        Ok(())
+    }
+
+    // ウィンドウなしで最後まで再生して検証する
+    pub fn run_headless(&self) -> Result<Simulation, ReplayError> {
+        let mut sim = self.simulation();
+        for &player in self.inputs.iter() {
+            if sim.is_over() {
+                break;
+            }
+            sim.step(StepInput { player });
+        }
+        self.verify(&sim)?;
+        Ok(sim)
+    }
+
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&[VERSION])?;
+        w.write_all(&self.seed.to_le_bytes())?;
+        w.write_all(&self.config.arena.width().to_le_bytes())?;
+        w.write_all(&self.config.arena.height().to_le_bytes())?;
+        w.write_all(&self.config.speed.to_le_bytes())?;
+        w.write_all(&(self.config.enemy_max as u32).to_le_bytes())?;
+        w.write_all(&self.config.food_interval.to_le_bytes())?;
+        for weight in self.config.direction_weight {
+            w.write_all(&weight.to_le_bytes())?;
+        }
+        for weight in self.config.brain_weight {
+            w.write_all(&weight.to_le_bytes())?;
+        }
+        w.write_all(&self.config.hunter_aggression.to_le_bytes())?;
+        w.write_all(&(self.config.stages.len() as u32).to_le_bytes())?;
+        for stage in self.config.stages.iter() {
+            write_stage(&mut w, stage)?;
+        }
+        w.write_all(&[self.food_count])?;
+        w.write_all(&self.digest.to_le_bytes())?;
+        w.write_all(&(self.inputs.len() as u32).to_le_bytes())?;
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|input| input.map_or(NO_INPUT, |dir| dir.get_num()))
+            .collect::<Vec<u8>>();
+        w.write_all(&inputs)?;
+        w.flush()
+    }
+
+    pub fn read_from<R: Read>(mut r: R) -> Result<Self, ReplayError> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(ReplayError::BadMagic);
+        }
+        let version = read_u8(&mut r)?;
+        if version != VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+
+        let seed = u64::from_le_bytes(read_array(&mut r)?);
+        let width = u32::from_le_bytes(read_array(&mut r)?);
+        let height = u32::from_le_bytes(read_array(&mut r)?);
+        let arena = Arena::new(width, height)
+            .ok_or(ReplayError::InvalidConfig("arena size out of range"))?;
+        let speed = u64::from_le_bytes(read_array(&mut r)?);
+        let enemy_max = u32::from_le_bytes(read_array(&mut r)?) as usize;
+        let food_interval = u64::from_le_bytes(read_array(&mut r)?);
+        let mut direction_weight = [0; 4];
+        for weight in direction_weight.iter_mut() {
+            *weight = i32::from_le_bytes(read_array(&mut r)?);
+        }
+        let mut brain_weight = [0; 5];
+        for weight in brain_weight.iter_mut() {
+            *weight = u32::from_le_bytes(read_array(&mut r)?);
+        }
+        let hunter_aggression = u32::from_le_bytes(read_array(&mut r)?);
+        let len = u32::from_le_bytes(read_array(&mut r)?);
+        let mut stages = Vec::new();
+        for _ in 0..len {
+            stages.push(read_stage(&mut r)?);
+        }
+        let config = SimConfig {
+            arena,
+            speed,
+            enemy_max,
+            food_interval,
+            direction_weight,
+            brain_weight,
+            hunter_aggression,
+            stages,
+        };
+        config.validate()?;
+        let food_count = read_u8(&mut r)?;
+        let digest = u64::from_le_bytes(read_array(&mut r)?);
+
+        // 壊れたファイルで長さだけ大きくても、先に確保しないよう読めた分だけ受け取る
+        let len = u32::from_le_bytes(read_array(&mut r)?) as u64;
+        let mut raw = Vec::new();
+        r.by_ref().take(len).read_to_end(&mut raw)?;
+        if raw.len() as u64 != len {
+            return Err(ReplayError::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+        let inputs = raw
+            .into_iter()
+            .enumerate()
+            .map(|(tick, value)| match value {
+                0..=3 => Ok(Some(Direction::num2dir(value))),
+                NO_INPUT => Ok(None),
+                _ => Err(ReplayError::InvalidInput { tick, value }),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Replay {
+            seed,
+            config,
+            inputs,
+            food_count,
+            digest,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ReplayError> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+// 段階の項目は「あるか」の 1 バイトと u64 の値で書く
+fn write_option<W: Write>(w: &mut W, value: Option<u64>) -> io::Result<()> {
+    w.write_all(&[value.is_some() as u8])?;
+    w.write_all(&value.unwrap_or(0).to_le_bytes())
+}
+
+fn read_option<R: Read>(r: &mut R) -> Result<Option<u64>, ReplayError> {
+    let present = read_u8(r)?;
+    let value = u64::from_le_bytes(read_array(r)?);
+    match present {
+        0 => Ok(None),
+        1 => Ok(Some(value)),
+        _ => Err(ReplayError::InvalidConfig("invalid stage")),
+    }
+}
+
+fn write_stage<W: Write>(w: &mut W, stage: &Stage) -> io::Result<()> {
+    w.write_all(&[stage.food])?;
+    write_option(w, stage.speed)?;
+    write_option(w, stage.enemy_max.map(|max| max as u64))?;
+    write_option(w, stage.food_interval)?;
+    for weight in stage.brain_weight {
+        write_option(w, weight.map(u64::from))?;
+    }
+    write_option(w, stage.hunter_aggression.map(u64::from))
+}
+
+fn read_stage<R: Read>(r: &mut R) -> Result<Stage, ReplayError> {
+    let food = read_u8(r)?;
+    let speed = read_option(r)?;
+    let enemy_max = read_option(r)?.map(|max| max as usize);
+    let food_interval = read_option(r)?;
+    let mut brain_weight = [None; 5];
+    for weight in brain_weight.iter_mut() {
+        *weight = read_u32_option(r)?;
+    }
+    let hunter_aggression = read_u32_option(r)?;
+    Ok(Stage {
+        food,
+        speed,
+        enemy_max,
+        food_interval,
+        brain_weight,
+        hunter_aggression,
+    })
+}
+
+fn read_u32_option<R: Read>(r: &mut R) -> Result<Option<u32>, ReplayError> {
+    read_option(r)?
+        .map(|value| u32::try_from(value).map_err(|_| ReplayError::InvalidConfig("invalid stage")))
+        .transpose()
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let [value] = read_array(r)?;
+    Ok(value)
+}
+
+fn read_array<R: Read, const N: usize>(r: &mut R) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 5 tick ごとに曲がりながら、終わるか上限まで遊んだゲーム
+    fn recorded_game() -> Simulation {
+        let turns = [
+            Direction::LEFT,
+            Direction::UP,
+            Direction::RIGHT,
+            Direction::DOWN,
+        ];
+        let mut sim = Simulation::new(SimConfig::default(), 7);
+        while !sim.is_over() && sim.tick() < 2000 {
+            let tick = sim.tick() as usize;
+            let player = (tick % 5 == 0).then(|| turns[tick / 5 % 4]);
+            sim.step(StepInput { player });
+        }
+        sim
+    }
+
+    fn written(replay: &Replay) -> Vec<u8> {
+        let mut buf = Vec::new();
+        replay.write_to(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn written_replay_plays_back_to_the_same_score() {
+        let sim = recorded_game();
+        let replay = Replay::read_from(&written(&Replay::from_simulation(&sim))[..]).unwrap();
+        assert_eq!(replay.config(), sim.config());
+        assert_eq!(replay.inputs(), sim.inputs());
+
+        let replayed = replay.run_headless().unwrap();
+        assert_eq!(replayed.tick(), sim.tick());
+        assert_eq!(replayed.score().food_count(), sim.score().food_count());
+        assert_eq!(replayed.score().digest(), sim.score().digest());
+    }
+
+    #[test]
+    fn different_trail_with_the_same_food_is_a_digest_mismatch() {
+        let mut replay = Replay::from_simulation(&recorded_game());
+        replay.digest ^= 1;
+        assert!(matches!(
+            replay.run_headless(),
+            Err(ReplayError::DigestMismatch)
+        ));
+    }
+
+    #[test]
+    fn inputs_after_the_end_are_an_input_count_mismatch() {
+        let sim = recorded_game();
+        assert!(sim.is_over());
+        let mut replay = Replay::from_simulation(&sim);
+        replay.inputs.push(None);
+        assert!(matches!(
+            replay.run_headless(),
+            Err(ReplayError::InputCountMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn truncated_replay_is_an_error() {
+        let buf = written(&Replay::from_simulation(&recorded_game()));
+        let err = Replay::read_from(&buf[..buf.len() - 1]).unwrap_err();
+        assert!(matches!(err, ReplayError::Io(_)), "{err}");
+    }
+}
@@ -1,41 +1,15 @@
-use bevy::prelude::*;
-use burn::{
-    backend::Wgpu,
-    tensor::{Tensor, TensorData},
-};
-use std::{
-    marker::PhantomData,
-    time::{Duration, Instant},
+use bevy::{
+    prelude::*,
+    tasks::{block_on, futures_lite::future, Task},
 };
+use std::{marker::PhantomData, path::PathBuf, sync::Mutex};
 
-use crate::components::Position;
-
-#[derive(Resource)]
-pub struct IntervalSetting<T> {
-    last_update: Instant,
-    phantom: PhantomData<T>,
-}
-
-impl<T> Default for IntervalSetting<T> {
-    fn default() -> Self {
-        Self {
-            last_update: Instant::now(),
-            phantom: PhantomData,
-        }
-    }
-}
-
-impl<T> IntervalSetting<T> {
-    pub fn check(&self, time: Instant, diff: Duration) -> bool {
-        time - self.last_update >= diff
-    }
-    pub fn update(&mut self, time: Instant) {
-        self.last_update = time;
-    }
-    pub fn reset(&mut self) {
-        self.last_update = Instant::now();
-    }
-}
+use crate::components::{Direction, Position};
+use crate::constants::{ARENA_HEIGHT, ARENA_MAX, ARENA_MIN, ARENA_WIDTH, DIGIT_SIZE};
+use crate::drawings::Drawing;
+use crate::hash::fnv1a;
+use crate::inference::{DigitRecognizer, ModelError, Prediction};
+use crate::replay::Replay;
 
 #[derive(Resource)]
 pub struct CounterSetting<T> {
@@ -61,11 +35,6 @@ impl<T> CounterSetting<T> {
             self.count += 1;
         }
     }
-    pub fn decrement(&mut self) {
-        if self.count > 0 {
-            self.count -= 1;
-        }
-    }
     pub fn reset(&mut self) {
         self.count = 0;
     }
@@ -74,6 +43,46 @@ impl<T> CounterSetting<T> {
     }
 }
 
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Arena {
+    width: u32,
+    height: u32,
+}
+
+impl Default for Arena {
+    fn default() -> Self {
+        Arena {
+            width: ARENA_WIDTH,
+            height: ARENA_HEIGHT,
+        }
+    }
+}
+
+impl Arena {
+    pub fn new(width: u32, height: u32) -> Option<Self> {
+        let range = ARENA_MIN..=ARENA_MAX;
+        if !range.contains(&width) || !range.contains(&height) {
+            return None;
+        }
+        Some(Arena { width, height })
+    }
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    pub fn in_arena(&self, x: i32, y: i32) -> bool {
+        0 <= x && x < self.width as i32 && 0 <= y && y < self.height as i32
+    }
+    pub fn in_enemy_arena(&self, x: i32, y: i32) -> bool {
+        -(self.width as i32 / 2) <= x
+            && x < 3 * self.width as i32 / 2
+            && -(self.height as i32 / 2) <= y
+            && y < 3 * self.height as i32 / 2
+    }
+}
+
 #[derive(Resource, Default)]
 pub struct FoodCenter {
     cnt: i32,
@@ -101,49 +110,386 @@ impl FoodCenter {
     }
 }
 
+#[derive(Resource, Default)]
+pub struct SeedSetting(pub Option<u64>);
+
+impl SeedSetting {
+    // 指定がなければゲームごとに新しいシードを使う
+    pub fn next_seed(&self) -> u64 {
+        self.0.unwrap_or_else(rand::random)
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct PlayerInput(Option<Direction>);
+
+impl PlayerInput {
+    pub fn set(&mut self, dir: Direction) {
+        self.0 = Some(dir);
+    }
+    pub fn take(&mut self) -> Option<Direction> {
+        self.0.take()
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct RecordSetting(pub Option<PathBuf>);
+
+// 終了時に軌跡を PNG で書き出す先
+#[derive(Resource, Default)]
+pub struct ExportSetting(pub Option<PathBuf>);
+
+#[derive(Resource)]
+pub struct ReplayPlayback {
+    replay: Replay,
+    cursor: usize,
+    // 検証に失敗したときの理由。スコア画面に出す
+    diverged: Option<String>,
+}
+
+impl ReplayPlayback {
+    pub fn new(replay: Replay) -> Self {
+        ReplayPlayback {
+            replay,
+            cursor: 0,
+            diverged: None,
+        }
+    }
+    pub fn set_diverged(&mut self, reason: String) {
+        self.diverged = Some(reason);
+    }
+    pub fn diverged(&self) -> Option<&str> {
+        self.diverged.as_deref()
+    }
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+    pub fn next_input(&mut self) -> Option<Direction> {
+        let input = self.replay.inputs().get(self.cursor).copied().flatten();
+        self.cursor += 1;
+        input
+    }
+}
+
+// 検証に失敗したリプレイの数。あれば終了コードを失敗にする
+#[derive(Resource, Default)]
+pub struct DivergedReplays(pub u32);
+
 #[derive(Resource)]
 pub struct MenuData {
     pub button_entity: Entity,
 }
 
-#[derive(Resource, Default)]
+#[derive(Resource)]
+pub struct ScoreScreenData {
+    pub root_entity: Entity,
+}
+
+/// スコア画面でプレイヤーが打ち込む「描いたつもりの数字」
+#[derive(Resource)]
+pub struct LabelInput {
+    grids: [[[f32; DIGIT_SIZE]; DIGIT_SIZE]; 2],
+    seed: u64,
+    digits: Vec<u8>,
+    saved: bool,
+}
+
+impl LabelInput {
+    pub fn new(score: &PlayerScore, seed: u64) -> Self {
+        LabelInput {
+            grids: [0, 1].map(|i| score.digit_grid(i)),
+            seed,
+            digits: Vec::new(),
+            saved: false,
+        }
+    }
+    pub fn push(&mut self, digit: u8) {
+        if !self.saved && self.digits.len() < 2 {
+            self.digits.push(digit);
+        }
+    }
+    pub fn pop(&mut self) {
+        if !self.saved {
+            self.digits.pop();
+        }
+    }
+    pub fn is_saved(&self) -> bool {
+        self.saved
+    }
+    // 2 桁そろっていれば保存する組を返し、以後の入力を受け付けない
+    pub fn take(&mut self, timestamp: u64) -> Option<Vec<Drawing>> {
+        if self.saved || self.digits.len() < 2 {
+            return None;
+        }
+        self.saved = true;
+        Some(
+            self.grids
+                .iter()
+                .zip(&self.digits)
+                .map(|(grid, &label)| Drawing {
+                    grid: *grid,
+                    label,
+                    seed: self.seed,
+                    timestamp,
+                })
+                .collect(),
+        )
+    }
+    pub fn text(&self) -> String {
+        let digits = (0..2)
+            .map(|i| self.digits.get(i).map_or("_".to_string(), u8::to_string))
+            .collect::<Vec<String>>()
+            .join(" ");
+        if self.saved {
+            format!("labelled as {digits}, thanks!")
+        } else {
+            format!("digits you meant to draw: {digits}  (type 0-9, Enter to save)")
+        }
+    }
+}
+
+type ModelResult = Result<DigitRecognizer, ModelError>;
+
+/// 数字認識モデルの読み込み状況
+#[derive(Resource)]
+pub enum DigitModelState {
+    Loading(Task<ModelResult>),
+    // モデルは Sync とは限らないので Mutex で包む
+    Ready(Mutex<DigitRecognizer>),
+    Failed(ModelError),
+}
+
+impl DigitModelState {
+    fn resolve(result: ModelResult) -> Self {
+        match result {
+            Ok(model) => DigitModelState::Ready(Mutex::new(model)),
+            Err(err) => {
+                eprintln!("digit recognition disabled: {err}");
+                DigitModelState::Failed(err)
+            }
+        }
+    }
+    // 読み込みが終わっていれば状態を進める
+    pub fn poll(&mut self) {
+        if let DigitModelState::Loading(task) = self {
+            if let Some(result) = block_on(future::poll_once(task)) {
+                *self = Self::resolve(result);
+            }
+        }
+    }
+    // 読み込みが終わるまで待つ
+    pub fn wait(&mut self) {
+        if let DigitModelState::Loading(task) = self {
+            let result = block_on(task);
+            *self = Self::resolve(result);
+        }
+    }
+    pub fn error(&self) -> Option<&ModelError> {
+        match self {
+            DigitModelState::Failed(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ScoreBreakdown {
+    pub food_count: u8,
+    // モデルが使えないときは None
+    pub predictions: Option<[Prediction; 2]>,
+    // 左右それぞれで認識された数字
+    pub digits: [u8; 2],
+    // 数字による加点。確信度で重み付けする場合は digits より小さくなる
+    pub bonus: [u32; 2],
+    pub total: u32,
+}
+
+impl ScoreBreakdown {
+    pub fn new(
+        food_count: u8,
+        predictions: Option<[Prediction; 2]>,
+        confidence_weighted: bool,
+    ) -> Self {
+        let digits = predictions
+            .as_ref()
+            .map_or([0; 2], |p| [p[0].digit(), p[1].digit()]);
+        let bonus = [0, 1].map(|i| match &predictions {
+            Some(p) if confidence_weighted => (digits[i] as f32 * p[i].confidence()).round() as u32,
+            _ => digits[i] as u32,
+        });
+        ScoreBreakdown {
+            food_count,
+            predictions,
+            digits,
+            bonus,
+            total: food_count as u32 + bonus[0] + bonus[1],
+        }
+    }
+}
+
+#[derive(Resource)]
 pub struct PlayerScore {
-    history: [[[f32; 28]; 28]; 2],
+    arena: Arena,
+    // history[x * height + y]
+    history: Vec<f32>,
     food_count: u8,
 }
 impl PlayerScore {
+    pub fn new(arena: Arena) -> Self {
+        PlayerScore {
+            arena,
+            history: vec![0.; arena.width() as usize * arena.height() as usize],
+            food_count: 0,
+        }
+    }
     pub fn add(&mut self, pos: Position) {
-        let i = pos.x() as usize / 28;
-        let x = pos.x() as usize % 28;
+        let x = pos.x() as usize;
         let y = pos.y() as usize;
 
-        self.history[i][x][y] += 1.;
+        self.history[x * self.arena.height() as usize + y] += 1.;
+    }
+    // アリーナの左右半分をそれぞれモデルの入力サイズに合わせる
+    // 画像と同じく grid[行][列] で、行 0 が上 (アリーナの y が大きい側)
+    // 細い軌跡が消えないよう、各セルに対応する範囲の最大値を取る
+    pub fn digit_grid(&self, i: usize) -> [[f32; DIGIT_SIZE]; DIGIT_SIZE] {
+        let height = self.arena.height() as usize;
+        let split = self.arena.width() as usize / 2;
+        let (offset, width) = if i == 0 {
+            (0, split)
+        } else {
+            (split, self.arena.width() as usize - split)
+        };
+
+        let mut grid = [[0.; DIGIT_SIZE]; DIGIT_SIZE];
+        for (row, cells) in grid.iter_mut().enumerate() {
+            for (col, cell) in cells.iter_mut().enumerate() {
+                *cell = cell_range(col, width)
+                    .flat_map(|x| {
+                        cell_range(row, height).map(move |r| (offset + x) * height + height - 1 - r)
+                    })
+                    .map(|idx| self.history[idx])
+                    .fold(0., f32::max);
+            }
+        }
+        grid
+    }
+    // アリーナ全体の訪問回数。画像と同じく上の行から
+    pub fn trail(&self) -> Vec<Vec<f32>> {
+        let height = self.arena.height() as usize;
+        (0..height)
+            .map(|row| {
+                (0..self.arena.width() as usize)
+                    .map(|x| self.history[x * height + height - 1 - row])
+                    .collect()
+            })
+            .collect()
     }
     pub fn increment(&mut self) {
         self.food_count += 1;
     }
-    pub fn get_score(&self) -> u8 {
-        type MyBackend = Wgpu<f32, i32>;
+    pub fn food_count(&self) -> u8 {
+        self.food_count
+    }
+    // リプレイ検証用
+    pub fn digest(&self) -> u64 {
+        fnv1a(
+            self.history
+                .iter()
+                .flat_map(|v| v.to_bits().to_le_bytes())
+                .chain([self.food_count]),
+        )
+    }
+    pub fn get_score(
+        &self,
+        model: Option<&DigitRecognizer>,
+        confidence_weighted: bool,
+    ) -> ScoreBreakdown {
+        let predictions = model.map(|model| [0, 1].map(|i| model.predict(&self.digit_grid(i))));
+        ScoreBreakdown::new(self.food_count, predictions, confidence_weighted)
+    }
+}
 
-        let device = burn::backend::wgpu::WgpuDevice::default();
-        let artifact_dir = "data";
+// 長さ len の軸を DIGIT_SIZE 等分したときの n 番目の範囲 (最低 1 セル)
+fn cell_range(n: usize, len: usize) -> std::ops::Range<usize> {
+    let start = n * len / DIGIT_SIZE;
+    let end = ((n + 1) * len / DIGIT_SIZE).max(start + 1);
+    start..end
+}
 
-        let mut score = self.food_count;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        for i in 0..2 {
-            let data = self.history[i]
-                .iter()
-                .flat_map(|row| row.iter())
-                .copied()
-                .collect();
-            let tensor_data = TensorData::new(data, [1, 28, 28]);
-            score += crate::inference::infer::<MyBackend>(
-                artifact_dir,
-                device.clone(),
-                Tensor::from_data(tensor_data.clone(), &device),
-            );
-        }
-
-        score
+    fn lit(grid: &[[f32; DIGIT_SIZE]; DIGIT_SIZE]) -> Vec<(usize, usize)> {
+        let mut cells = Vec::new();
+        for (row, cells_in_row) in grid.iter().enumerate() {
+            for (col, &v) in cells_in_row.iter().enumerate() {
+                if v > 0. {
+                    cells.push((row, col));
+                }
+            }
+        }
+        cells
+    }
+
+    // 1 セルが 1 画素になる大きさ
+    fn score() -> PlayerScore {
+        PlayerScore::new(Arena::new(DIGIT_SIZE as u32 * 2, DIGIT_SIZE as u32).unwrap())
+    }
+
+    #[test]
+    fn arena_size_is_bounded_on_both_sides() {
+        assert!(Arena::new(ARENA_MIN, ARENA_MAX).is_some());
+        assert!(Arena::new(ARENA_MIN - 1, ARENA_MIN).is_none());
+        assert!(Arena::new(ARENA_MIN, ARENA_MAX + 1).is_none());
+        assert!(Arena::new(u32::MAX, u32::MAX).is_none());
+    }
+
+    #[test]
+    fn top_left_of_arena_is_first_pixel() {
+        let mut score = score();
+        score.add(Position::new(0, DIGIT_SIZE as i32 - 1));
+        assert_eq!(lit(&score.digit_grid(0)), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn bottom_right_of_arena_is_last_pixel() {
+        let mut score = score();
+        score.add(Position::new(DIGIT_SIZE as i32 * 2 - 1, 0));
+        assert!(lit(&score.digit_grid(0)).is_empty());
+        assert_eq!(
+            lit(&score.digit_grid(1)),
+            vec![(DIGIT_SIZE - 1, DIGIT_SIZE - 1)]
+        );
+    }
+
+    #[test]
+    fn moving_up_goes_to_earlier_rows() {
+        let mut score = score();
+        let mut pos = Position::new(3, 10);
+        score.add(pos);
+        pos += Direction::UP.to_pos(1);
+        score.add(pos);
+        pos += Direction::RIGHT.to_pos(1);
+        score.add(pos);
+        let row = DIGIT_SIZE - 1 - 10;
+        assert_eq!(
+            lit(&score.digit_grid(0)),
+            vec![(row - 1, 3), (row - 1, 4), (row, 3)]
+        );
+    }
+
+    #[test]
+    fn larger_arena_keeps_orientation() {
+        let arena = Arena::new(80, 50).unwrap();
+        let mut score = PlayerScore::new(arena);
+        // 左半分の左上隅と右下隅
+        score.add(Position::new(0, arena.height() as i32 - 1));
+        score.add(Position::new(arena.width() as i32 / 2 - 1, 0));
+        let grid = score.digit_grid(0);
+        assert!(grid[0][0] > 0.);
+        assert!(grid[DIGIT_SIZE - 1][DIGIT_SIZE - 1] > 0.);
+        assert_eq!(grid[0][DIGIT_SIZE - 1], 0.);
+        assert_eq!(grid[DIGIT_SIZE - 1][0], 0.);
     }
 }
@@ -0,0 +1,531 @@
+use std::{fmt, io, path::Path, time::Duration};
+
+use bevy::{color::Color, prelude::Resource};
+use serde::Deserialize;
+
+use crate::constants::{
+    ARENA_HEIGHT, ARENA_MAX, ARENA_MIN, ARENA_WIDTH, BODY_SIZE, BRAIN_WEIGHT, DIRECTION_WEIGHT,
+    ENEMY_BODY_COLOR, ENEMY_HEAD_COLOR, ENEMY_MAX, FOOD_COLOR, FOOD_INTERVAL_TICKS, FOOD_SIZE,
+    HEAD_SIZE, HUNTER_AGGRESSION, PLAYER_BODY_COLOR, PLAYER_HEAD_COLOR, SNAKE_SPEED, WORLD_COLOR,
+};
+use crate::resources::Arena;
+use crate::simulation::{ConfigError, SimConfig, Stage};
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Invalid { key: String, reason: String },
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "failed to read settings: {err}"),
+            Self::Parse(err) => write!(f, "failed to parse settings: {err}"),
+            Self::Invalid { key, reason } => write!(f, "invalid setting `{key}`: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+fn invalid(key: impl Into<String>, reason: impl Into<String>) -> SettingsError {
+    SettingsError::Invalid {
+        key: key.into(),
+        reason: reason.into(),
+    }
+}
+
+// SimConfig の項目名を設定ファイルのキーに直す
+fn base_key(err: &ConfigError) -> &'static str {
+    match err.field {
+        "food_interval" => "food.interval_ticks",
+        "direction_weight" => "enemy.direction_weight",
+        "brain_weight" => "enemy.brains",
+        "hunter_aggression" => "enemy.hunter_aggression",
+        field => field,
+    }
+}
+
+fn stage_key(err: &ConfigError) -> &'static str {
+    match err.field {
+        "brain_weight" => "brains",
+        field => field,
+    }
+}
+
+fn rgb(color: Color) -> [f32; 3] {
+    let color = color.to_linear();
+    [color.red, color.green, color.blue]
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArenaSettings {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for ArenaSettings {
+    fn default() -> Self {
+        ArenaSettings {
+            width: ARENA_WIDTH,
+            height: ARENA_HEIGHT,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnemySettings {
+    pub max: usize,
+    // [上, 右, 下, 左]
+    pub direction_weight: [i32; 4],
+    // SimConfig::hunter_aggression になる
+    pub hunter_aggression: u32,
+    pub brains: BrainSettings,
+}
+
+impl Default for EnemySettings {
+    fn default() -> Self {
+        EnemySettings {
+            max: ENEMY_MAX,
+            direction_weight: DIRECTION_WEIGHT,
+            hunter_aggression: HUNTER_AGGRESSION,
+            brains: BrainSettings::default(),
+        }
+    }
+}
+
+// 敵が生まれるときにどの操縦を選ぶかの重み
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct BrainSettings {
+    pub walker: u32,
+    pub chaser: u32,
+    pub avoider: u32,
+    pub pathfinder: u32,
+    pub hunter: u32,
+}
+
+impl Default for BrainSettings {
+    fn default() -> Self {
+        let [walker, chaser, avoider, pathfinder, hunter] = BRAIN_WEIGHT;
+        BrainSettings {
+            walker,
+            chaser,
+            avoider,
+            pathfinder,
+            hunter,
+        }
+    }
+}
+
+impl BrainSettings {
+    // BrainKind::ALL の順
+    pub fn weights(&self) -> [u32; 5] {
+        [
+            self.walker,
+            self.chaser,
+            self.avoider,
+            self.pathfinder,
+            self.hunter,
+        ]
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct FoodSettings {
+    pub interval_ticks: u64,
+}
+
+impl Default for FoodSettings {
+    fn default() -> Self {
+        FoodSettings {
+            interval_ticks: FOOD_INTERVAL_TICKS,
+        }
+    }
+}
+
+/// メニューで選ぶ難易度。段階は食べた餌の数で順に重なる
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DifficultySettings {
+    pub name: String,
+    #[serde(default)]
+    pub stages: Vec<StageSettings>,
+}
+
+// 書かなかった項目は前の段階 (最初は上の設定) のまま
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct StageSettings {
+    pub food: u8,
+    pub speed: Option<u64>,
+    pub enemy_max: Option<usize>,
+    pub food_interval: Option<u64>,
+    pub hunter_aggression: Option<u32>,
+    pub brains: StageBrainSettings,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct StageBrainSettings {
+    pub walker: Option<u32>,
+    pub chaser: Option<u32>,
+    pub avoider: Option<u32>,
+    pub pathfinder: Option<u32>,
+    pub hunter: Option<u32>,
+}
+
+impl StageSettings {
+    fn to_stage(&self) -> Stage {
+        let brains = &self.brains;
+        Stage {
+            food: self.food,
+            speed: self.speed,
+            enemy_max: self.enemy_max,
+            food_interval: self.food_interval,
+            brain_weight: [
+                brains.walker,
+                brains.chaser,
+                brains.avoider,
+                brains.pathfinder,
+                brains.hunter,
+            ],
+            hunter_aggression: self.hunter_aggression,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScoringSettings {
+    // 数字の加点にモデルの確信度を掛ける
+    pub confidence_weighted: bool,
+}
+
+// 線形 RGB
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColorSettings {
+    pub world: [f32; 3],
+    pub player_head: [f32; 3],
+    pub player_body: [f32; 3],
+    pub enemy_head: [f32; 3],
+    pub enemy_body: [f32; 3],
+    pub food: [f32; 3],
+}
+
+impl Default for ColorSettings {
+    fn default() -> Self {
+        ColorSettings {
+            world: rgb(WORLD_COLOR),
+            player_head: rgb(PLAYER_HEAD_COLOR),
+            player_body: rgb(PLAYER_BODY_COLOR),
+            enemy_head: rgb(ENEMY_HEAD_COLOR),
+            enemy_body: rgb(ENEMY_BODY_COLOR),
+            food: rgb(FOOD_COLOR),
+        }
+    }
+}
+
+// セルに対する比率
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SizeSettings {
+    pub head: f32,
+    pub body: f32,
+    pub food: f32,
+}
+
+impl Default for SizeSettings {
+    fn default() -> Self {
+        SizeSettings {
+            head: HEAD_SIZE,
+            body: BODY_SIZE,
+            food: FOOD_SIZE,
+        }
+    }
+}
+
+#[derive(Resource, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameSettings {
+    pub seed: Option<u64>,
+    pub speed: u64,
+    // difficulties の name。なければ最初のもの
+    pub difficulty: Option<String>,
+    pub arena: ArenaSettings,
+    pub enemy: EnemySettings,
+    pub food: FoodSettings,
+    pub scoring: ScoringSettings,
+    pub colors: ColorSettings,
+    pub sizes: SizeSettings,
+    pub difficulties: Vec<DifficultySettings>,
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        GameSettings {
+            seed: None,
+            speed: SNAKE_SPEED,
+            difficulty: None,
+            arena: ArenaSettings::default(),
+            enemy: EnemySettings::default(),
+            food: FoodSettings::default(),
+            scoring: ScoringSettings::default(),
+            colors: ColorSettings::default(),
+            sizes: SizeSettings::default(),
+            difficulties: Vec::new(),
+        }
+    }
+}
+
+impl GameSettings {
+    pub fn load(path: &Path) -> Result<Self, SettingsError> {
+        let text = std::fs::read_to_string(path).map_err(SettingsError::Io)?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, SettingsError> {
+        let settings: GameSettings = toml::from_str(text).map_err(SettingsError::Parse)?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        if !(ARENA_MIN..=ARENA_MAX).contains(&self.arena.width) {
+            return Err(invalid(
+                "arena.width",
+                format!("must be between {ARENA_MIN} and {ARENA_MAX}"),
+            ));
+        }
+        if !(ARENA_MIN..=ARENA_MAX).contains(&self.arena.height) {
+            return Err(invalid(
+                "arena.height",
+                format!("must be between {ARENA_MIN} and {ARENA_MAX}"),
+            ));
+        }
+        self.base_config()
+            .validate()
+            .map_err(|err| invalid(base_key(&err), err.reason))?;
+        self.validate_difficulties()?;
+
+        let colors = [
+            ("colors.world", self.colors.world),
+            ("colors.player_head", self.colors.player_head),
+            ("colors.player_body", self.colors.player_body),
+            ("colors.enemy_head", self.colors.enemy_head),
+            ("colors.enemy_body", self.colors.enemy_body),
+            ("colors.food", self.colors.food),
+        ];
+        for (key, color) in colors {
+            if color.iter().any(|c| !c.is_finite() || *c < 0.) {
+                return Err(invalid(key, "components must be finite and not negative"));
+            }
+        }
+
+        let sizes = [
+            ("sizes.head", self.sizes.head),
+            ("sizes.body", self.sizes.body),
+            ("sizes.food", self.sizes.food),
+        ];
+        for (key, size) in sizes {
+            if size.is_nan() || size <= 0. || size > 1. {
+                return Err(invalid(key, "must be greater than 0 and at most 1"));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_difficulties(&self) -> Result<(), SettingsError> {
+        for (i, difficulty) in self.difficulties.iter().enumerate() {
+            let key = |field: &str| format!("difficulties[{i}].{field}");
+            if difficulty.name.trim().is_empty() {
+                return Err(invalid(key("name"), "must not be empty"));
+            }
+            if self.difficulties[..i]
+                .iter()
+                .any(|other| other.name.eq_ignore_ascii_case(&difficulty.name))
+            {
+                return Err(invalid(
+                    key("name"),
+                    format!("`{}` is used twice", difficulty.name),
+                ));
+            }
+            let config = SimConfig {
+                stages: difficulty
+                    .stages
+                    .iter()
+                    .map(StageSettings::to_stage)
+                    .collect(),
+                ..self.base_config()
+            };
+            config.validate().map_err(|err| match err.stage {
+                Some(j) => invalid(key(&format!("stages[{j}].{}", stage_key(&err))), err.reason),
+                None => invalid(base_key(&err), err.reason),
+            })?;
+        }
+        if let Some(name) = &self.difficulty {
+            if self.find_difficulty(name).is_none() {
+                return Err(invalid(
+                    "difficulty",
+                    format!("no difficulty named `{name}`"),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn find_difficulty(&self, name: &str) -> Option<&DifficultySettings> {
+        self.difficulties
+            .iter()
+            .find(|difficulty| difficulty.name.eq_ignore_ascii_case(name))
+    }
+
+    // 速さを固定するときは難易度の段階で変えない
+    pub fn clear_stage_speeds(&mut self) {
+        for difficulty in self.difficulties.iter_mut() {
+            for stage in difficulty.stages.iter_mut() {
+                stage.speed = None;
+            }
+        }
+    }
+
+    // 選ばれている難易度
+    pub fn selected_difficulty(&self) -> Option<&DifficultySettings> {
+        match &self.difficulty {
+            Some(name) => self.find_difficulty(name),
+            None => self.difficulties.first(),
+        }
+    }
+
+    pub fn tick(&self) -> Duration {
+        Duration::from_millis(600 / self.speed)
+    }
+
+    pub fn arena(&self) -> Arena {
+        Arena::new(self.arena.width, self.arena.height).unwrap()
+    }
+
+    // 難易度の段階を含まない設定
+    fn base_config(&self) -> SimConfig {
+        SimConfig {
+            arena: self.arena(),
+            speed: self.speed,
+            enemy_max: self.enemy.max,
+            food_interval: self.food.interval_ticks,
+            direction_weight: self.enemy.direction_weight,
+            brain_weight: self.enemy.brains.weights(),
+            hunter_aggression: self.enemy.hunter_aggression,
+            stages: Vec::new(),
+        }
+    }
+
+    pub fn sim_config(&self) -> SimConfig {
+        let stages = self
+            .selected_difficulty()
+            .map(|difficulty| {
+                difficulty
+                    .stages
+                    .iter()
+                    .map(StageSettings::to_stage)
+                    .collect()
+            })
+            .unwrap_or_default();
+        SimConfig {
+            stages,
+            ..self.base_config()
+        }
+    }
+
+    pub fn color(rgb: [f32; 3]) -> Color {
+        Color::linear_rgb(rgb[0], rgb[1], rgb[2])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 設定ファイルとして読んで、だめだと言われたキーを返す
+    fn invalid_key(text: &str) -> String {
+        match GameSettings::parse(text) {
+            Err(SettingsError::Invalid { key, .. }) => key,
+            other => panic!("expected an invalid setting, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn shipped_settings_are_valid() {
+        GameSettings::parse(include_str!("../settings.toml")).unwrap();
+    }
+
+    #[test]
+    fn invalid_top_level_values_name_their_key() {
+        assert_eq!(invalid_key("speed = 0"), "speed");
+        assert_eq!(invalid_key("[arena]\nwidth = 100000"), "arena.width");
+        assert_eq!(
+            invalid_key("[enemy]\nhunter_aggression = 101"),
+            "enemy.hunter_aggression"
+        );
+        assert_eq!(
+            invalid_key("[enemy]\ndirection_weight = [0, 0, 0, 0]"),
+            "enemy.direction_weight"
+        );
+        assert_eq!(
+            invalid_key("[food]\ninterval_ticks = 0"),
+            "food.interval_ticks"
+        );
+        assert_eq!(invalid_key("difficulty = \"Missing\""), "difficulty");
+    }
+
+    #[test]
+    fn invalid_stages_name_the_difficulty_and_stage() {
+        let text = r#"
+            [[difficulties]]
+            name = "Easy"
+
+            [[difficulties]]
+            name = "Hard"
+            stages = [{ food = 0 }, { food = 5, speed = 0 }]
+        "#;
+        assert_eq!(invalid_key(text), "difficulties[1].stages[1].speed");
+
+        let text = r#"
+            [[difficulties]]
+            name = "Easy"
+            stages = [{ food = 5 }, { food = 3 }]
+        "#;
+        assert_eq!(invalid_key(text), "difficulties[0].stages[1].food");
+
+        let text = r#"
+            [enemy.brains]
+            walker = 1
+            chaser = 0
+            avoider = 0
+            pathfinder = 0
+            hunter = 0
+
+            [[difficulties]]
+            name = "Easy"
+            stages = [{ food = 0, brains = { walker = 0 } }]
+        "#;
+        assert_eq!(invalid_key(text), "difficulties[0].stages[0].brains");
+    }
+
+    #[test]
+    fn difficulty_names_must_be_unique() {
+        let text = r#"
+            [[difficulties]]
+            name = "Easy"
+
+            [[difficulties]]
+            name = "easy"
+        "#;
+        assert_eq!(invalid_key(text), "difficulties[1].name");
+    }
+}
@@ -0,0 +1,824 @@
+use std::{fmt, time::Duration};
+
+use bevy::prelude::Resource;
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    Rng, RngCore, SeedableRng,
+};
+use rand_chacha::ChaCha8Rng;
+
+use crate::brain::{BrainKind, SnakeBrain, WorldView};
+use crate::components::{Direction, Position};
+use crate::constants::{
+    BRAIN_WEIGHT, DIRECTION_WEIGHT, ENEMY_MAX, FOOD_INTERVAL_TICKS, HUNTER_AGGRESSION, PATH_BUDGET,
+    SNAKE_SPEED,
+};
+use crate::resources::{Arena, FoodCenter, PlayerScore};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct SnakeId(u32);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct FoodId(u32);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SnakeKind {
+    Player,
+    Enemy,
+}
+
+#[derive(Debug)]
+pub struct SimSnake {
+    id: SnakeId,
+    kind: SnakeKind,
+    direction: Direction,
+    // 先頭が頭
+    body: Vec<Position>,
+    // 敵だけが持つ。向きを決める間だけ取り出す
+    brain: Option<Box<dyn SnakeBrain>>,
+}
+
+impl SimSnake {
+    fn new(
+        id: SnakeId,
+        kind: SnakeKind,
+        body: Vec<Position>,
+        brain: Option<Box<dyn SnakeBrain>>,
+    ) -> Self {
+        SimSnake {
+            id,
+            kind,
+            direction: Direction::UP,
+            body,
+            brain,
+        }
+    }
+    pub fn id(&self) -> SnakeId {
+        self.id
+    }
+    pub fn is_player(&self) -> bool {
+        self.kind == SnakeKind::Player
+    }
+    pub fn head(&self) -> Position {
+        self.body[0]
+    }
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+    pub fn body(&self) -> &[Position] {
+        &self.body
+    }
+    pub fn len(&self) -> usize {
+        self.body.len()
+    }
+    // 今の向きに 1 マス進んだときの頭
+    fn next_head(&self) -> Position {
+        self.head() + self.direction.to_pos(1)
+    }
+}
+
+// 他のモジュールのテストで盤面を組み立てる
+#[cfg(test)]
+impl SimSnake {
+    pub(crate) fn enemy(id: u32, direction: Direction, body: Vec<Position>) -> Self {
+        let mut snake = SimSnake::new(SnakeId(id), SnakeKind::Enemy, body, None);
+        snake.direction = direction;
+        snake
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SimFood {
+    id: FoodId,
+    pos: Position,
+}
+
+impl SimFood {
+    pub fn id(&self) -> FoodId {
+        self.id
+    }
+    pub fn pos(&self) -> Position {
+        self.pos
+    }
+}
+
+#[cfg(test)]
+impl SimFood {
+    pub(crate) fn at(id: u32, pos: Position) -> Self {
+        SimFood {
+            id: FoodId(id),
+            pos,
+        }
+    }
+}
+
+// StdRng は rand のバージョン間で再現性が保証されないため ChaCha を直接使う
+#[derive(Clone, Debug)]
+pub struct GameRng {
+    seed: u64,
+    rng: ChaCha8Rng,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        GameRng {
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+/// SimConfig の値が使えないとき。field は SimConfig か Stage の項目名
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ConfigError {
+    // 問題のある段階の番号。None なら段階の外
+    pub stage: Option<usize>,
+    pub field: &'static str,
+    pub reason: &'static str,
+}
+
+impl ConfigError {
+    fn new(field: &'static str, reason: &'static str) -> Self {
+        ConfigError {
+            stage: None,
+            field,
+            reason,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.stage {
+            Some(i) => write!(f, "stages[{i}].{}: {}", self.field, self.reason),
+            None => write!(f, "{}: {}", self.field, self.reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// プレイヤーが餌を食べた数で切り替わる調整。None の項目は前の値のまま
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Stage {
+    // 食べた餌がこの数になったら適用する
+    pub food: u8,
+    pub speed: Option<u64>,
+    pub enemy_max: Option<usize>,
+    pub food_interval: Option<u64>,
+    // BrainKind::ALL の順
+    pub brain_weight: [Option<u32>; 5],
+    pub hunter_aggression: Option<u32>,
+}
+
+impl Stage {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.speed.is_some_and(|speed| !(1..=600).contains(&speed)) {
+            return Err(ConfigError::new("speed", "must be between 1 and 600"));
+        }
+        if self.food_interval == Some(0) {
+            return Err(ConfigError::new("food_interval", "must be at least 1"));
+        }
+        if self
+            .hunter_aggression
+            .is_some_and(|aggression| aggression > 100)
+        {
+            return Err(ConfigError::new(
+                "hunter_aggression",
+                "must be between 0 and 100",
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, config: &mut SimConfig) {
+        config.speed = self.speed.unwrap_or(config.speed);
+        config.enemy_max = self.enemy_max.unwrap_or(config.enemy_max);
+        config.food_interval = self.food_interval.unwrap_or(config.food_interval);
+        for (weight, stage) in config.brain_weight.iter_mut().zip(self.brain_weight) {
+            *weight = stage.unwrap_or(*weight);
+        }
+        config.hunter_aggression = self.hunter_aggression.unwrap_or(config.hunter_aggression);
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SimConfig {
+    pub arena: Arena,
+    // 1 tick は 600 / speed ミリ秒
+    pub speed: u64,
+    pub enemy_max: usize,
+    // 何 tick ごとに餌を出すか
+    pub food_interval: u64,
+    // [上, 右, 下, 左]
+    pub direction_weight: [i32; 4],
+    // BrainKind::ALL の順
+    pub brain_weight: [u32; 5],
+    // hunter が各 tick にプレイヤーを追う確率 (%)
+    pub hunter_aggression: u32,
+    // food の昇順
+    pub stages: Vec<Stage>,
+}
+
+impl SimConfig {
+    /// 設定ファイルからでもリプレイからでも同じ範囲で確かめる。アリーナは Arena::new で見る
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(1..=600).contains(&self.speed) {
+            return Err(ConfigError::new("speed", "must be between 1 and 600"));
+        }
+        if self.food_interval == 0 {
+            return Err(ConfigError::new("food_interval", "must be at least 1"));
+        }
+        if self.direction_weight.iter().any(|&w| w < 0) {
+            return Err(ConfigError::new(
+                "direction_weight",
+                "weights must not be negative",
+            ));
+        }
+        if self.direction_weight.iter().all(|&w| w == 0) {
+            return Err(ConfigError::new(
+                "direction_weight",
+                "at least one weight must be positive",
+            ));
+        }
+        if self.brain_weight.iter().all(|&w| w == 0) {
+            return Err(ConfigError::new(
+                "brain_weight",
+                "at least one weight must be positive",
+            ));
+        }
+        if self.hunter_aggression > 100 {
+            return Err(ConfigError::new(
+                "hunter_aggression",
+                "must be between 0 and 100",
+            ));
+        }
+        // 段階を順に重ねたときに、どこかで使えない値にならないかを見る
+        let mut current = self.clone();
+        for (i, stage) in self.stages.iter().enumerate() {
+            let at = |err: ConfigError| ConfigError {
+                stage: Some(i),
+                ..err
+            };
+            if i > 0 && stage.food < self.stages[i - 1].food {
+                return Err(at(ConfigError::new(
+                    "food",
+                    "must not be less than the previous stage",
+                )));
+            }
+            stage.validate().map_err(at)?;
+            stage.apply(&mut current);
+            if current.brain_weight.iter().all(|&w| w == 0) {
+                return Err(at(ConfigError::new(
+                    "brain_weight",
+                    "leaves no enemy kind to spawn",
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn tick(&self) -> Duration {
+        Duration::from_millis(600 / self.speed)
+    }
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            arena: Arena::default(),
+            speed: SNAKE_SPEED,
+            enemy_max: ENEMY_MAX,
+            food_interval: FOOD_INTERVAL_TICKS,
+            direction_weight: DIRECTION_WEIGHT,
+            brain_weight: BRAIN_WEIGHT,
+            hunter_aggression: HUNTER_AGGRESSION,
+            stages: Vec::new(),
+        }
+    }
+}
+
+/// 1 tick で蛇がどうなるか
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Move {
+    // 頭が head に進む。餌を食べたら尻尾は残って 1 つ伸びる
+    To { head: Position, eats: bool },
+    Dead,
+}
+
+/// 全員の次の頭を先に決めてから、同時に動いたものとして当たり判定をする。
+/// - アリーナの外 (敵は敵の範囲の外) に出た頭は死ぬ
+/// - 2 匹以上の頭が同じマスに入ったら全員死ぬ。そこに餌があっても誰も食べない
+/// - 動く前の体があるマスに入った頭は死ぬ。頭同士がすれ違う場合もこれで両方死ぬ
+/// - ただし餌を食べない蛇の尻尾はこの tick に空くので、自分のものも含めて入ってよい。
+///   上の 2 つで死ぬ蛇は食べないので、その尻尾にも入れる
+fn resolve_moves(arena: Arena, snakes: &[SimSnake], foods: &[SimFood]) -> Vec<Move> {
+    let heads = snakes.iter().map(SimSnake::next_head).collect::<Vec<_>>();
+    // 体に関係なく決まる死に方を先に見る
+    let alive = snakes
+        .iter()
+        .enumerate()
+        .map(|(i, snake)| {
+            let head = heads[i];
+            let in_bounds = match snake.kind {
+                SnakeKind::Player => arena.in_arena(head.x(), head.y()),
+                SnakeKind::Enemy => arena.in_enemy_arena(head.x(), head.y()),
+            };
+            let head_on = heads
+                .iter()
+                .enumerate()
+                .any(|(j, &other)| j != i && other == head);
+            in_bounds && !head_on
+        })
+        .collect::<Vec<_>>();
+    let eats = heads
+        .iter()
+        .zip(alive.iter())
+        .map(|(&head, &alive)| alive && foods.iter().any(|food| food.pos == head))
+        .collect::<Vec<_>>();
+    // 頭が入ると死ぬマス。食べない蛇は尻尾を除く
+    let occupied = snakes
+        .iter()
+        .zip(eats.iter())
+        .flat_map(|(snake, &eats)| {
+            let len = if eats { snake.len() } else { snake.len() - 1 };
+            snake.body[..len].iter().copied()
+        })
+        .collect::<Vec<_>>();
+
+    heads
+        .iter()
+        .enumerate()
+        .map(|(i, &head)| {
+            if !alive[i] || occupied.contains(&head) {
+                Move::Dead
+            } else {
+                Move::To {
+                    head,
+                    eats: eats[i],
+                }
+            }
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct StepInput {
+    pub player: Option<Direction>,
+}
+
+/// 描画から切り離したゲーム本体。`step` を呼んだ分だけ進む。
+#[derive(Resource)]
+pub struct Simulation {
+    config: SimConfig,
+    // config に今までの段階を重ねたもの。ゲーム中はこちらを使う
+    current: SimConfig,
+    // 適用済みの段階の数
+    stage: usize,
+    elapsed: Duration,
+    snakes: Vec<SimSnake>,
+    foods: Vec<SimFood>,
+    center: FoodCenter,
+    score: PlayerScore,
+    rng: GameRng,
+    inputs: Vec<Option<Direction>>,
+    tick: u64,
+    next_id: u32,
+    game_over: bool,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self::new(SimConfig::default(), rand::random())
+    }
+}
+
+impl Simulation {
+    pub fn new(config: SimConfig, seed: u64) -> Self {
+        let mut sim = Simulation {
+            current: config.clone(),
+            stage: 0,
+            elapsed: Duration::ZERO,
+            score: PlayerScore::new(config.arena),
+            config,
+            snakes: Vec::new(),
+            foods: Vec::new(),
+            center: FoodCenter::default(),
+            rng: GameRng::new(seed),
+            inputs: Vec::new(),
+            tick: 0,
+            next_id: 0,
+            game_over: false,
+        };
+        sim.advance_stage();
+        sim.spawn_player();
+        sim
+    }
+
+    // 食べた数が届いた段階をすべて重ねる
+    fn advance_stage(&mut self) {
+        let food = self.score.food_count();
+        while let Some(stage) = self.config.stages.get(self.stage) {
+            if stage.food > food {
+                break;
+            }
+            stage.apply(&mut self.current);
+            self.stage += 1;
+        }
+    }
+
+    fn next_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    fn spawn_player(&mut self) {
+        let x = self.current.arena.width() as i32 / 2;
+        let y = self.current.arena.height() as i32 / 2;
+        let id = SnakeId(self.next_id());
+        self.snakes.push(SimSnake::new(
+            id,
+            SnakeKind::Player,
+            vec![Position::new(x, y), Position::new(x, y - 1)],
+            None,
+        ));
+    }
+
+    fn spawn_enemy(&mut self) -> Option<SnakeId> {
+        if self.enemy_count() >= self.current.enemy_max {
+            return None;
+        }
+
+        let width = self.current.arena.width();
+        let height = self.current.arena.height();
+        let rng = &mut self.rng;
+        let mut px = rng.gen_range(-1..3);
+        let mut py = rng.gen_range(-1..3);
+        while (px == 0 || px == 1) && (py == 0 || py == 1) {
+            px = rng.gen_range(-1..3);
+            py = rng.gen_range(-1..3);
+        }
+        let x = px * (width as i32 / 2) + rng.gen_range(0..width) as i32;
+        let y = (height as i32 - 4).min(py * (height as i32 / 2) + rng.gen_range(0..height) as i32); // 体が下に続くため
+
+        let brain = WeightedIndex::new(self.current.brain_weight)
+            .map_or(BrainKind::Walker, |dist| BrainKind::ALL[dist.sample(rng)]);
+
+        let id = SnakeId(self.next_id());
+        let body = (0..4).map(|i| Position::new(x, y - i)).collect();
+        self.snakes.push(SimSnake::new(
+            id,
+            SnakeKind::Enemy,
+            body,
+            Some(brain.build(&self.current)),
+        ));
+        Some(id)
+    }
+
+    fn spawn_food(&mut self) -> FoodId {
+        let pos = Position::new(
+            (self.rng.gen::<f32>() * self.current.arena.width() as f32) as i32,
+            (self.rng.gen::<f32>() * self.current.arena.height() as f32) as i32,
+        );
+
+        let id = FoodId(self.next_id());
+        self.foods.push(SimFood { id, pos });
+        self.center.add(&pos);
+        id
+    }
+
+    pub fn step(&mut self, input: StepInput) {
+        if self.game_over {
+            return;
+        }
+        self.inputs.push(input.player);
+        self.tick += 1;
+        self.elapsed += self.current.tick();
+        self.spawn_enemy();
+        if self.tick % self.current.food_interval == 0 {
+            self.spawn_food();
+        }
+        self.steer(input);
+
+        let moves = resolve_moves(self.current.arena, &self.snakes, &self.foods);
+        // プレイヤーが死んだ tick は誰も動かさない
+        if self
+            .snakes
+            .iter()
+            .zip(moves.iter())
+            .any(|(snake, step)| snake.is_player() && *step == Move::Dead)
+        {
+            self.game_over = true;
+            return;
+        }
+
+        let mut dead = Vec::new();
+        for (snake, step) in self.snakes.iter_mut().zip(moves) {
+            let Move::To { head, eats } = step else {
+                dead.push(snake.id);
+                continue;
+            };
+            snake.body.insert(0, head);
+            if eats {
+                let i = self.foods.iter().position(|food| food.pos == head).unwrap();
+                let food = self.foods.remove(i);
+                self.center.remove(&food.pos);
+            } else {
+                snake.body.pop();
+            }
+            if snake.is_player() {
+                self.score.add(head);
+                if eats {
+                    self.score.increment();
+                }
+            }
+        }
+
+        self.snakes.retain(|snake| !dead.contains(&snake.id));
+        self.advance_stage();
+    }
+
+    fn steer(&mut self, input: StepInput) {
+        if let (Some(player), Some(dir)) = (
+            self.snakes.iter_mut().find(|snake| snake.is_player()),
+            input.player,
+        ) {
+            if dir != player.direction.opposite() {
+                player.direction = dir;
+            }
+        }
+
+        // 全員が同じ盤面を見て決めるよう、操縦をいったん取り出してから向きを決める
+        let mut brains = self
+            .snakes
+            .iter_mut()
+            .map(|snake| snake.brain.take())
+            .collect::<Vec<_>>();
+        let world = WorldView::new(
+            self.current.arena,
+            &self.snakes,
+            &self.foods,
+            self.center.get_pos(),
+            PATH_BUDGET,
+        );
+        let directions = brains
+            .iter_mut()
+            .zip(self.snakes.iter())
+            .map(|(brain, snake)| {
+                brain
+                    .as_mut()
+                    .map(|brain| brain.choose(snake, &world, &mut self.rng))
+            })
+            .collect::<Vec<_>>();
+        for ((snake, brain), direction) in self.snakes.iter_mut().zip(brains).zip(directions) {
+            snake.brain = brain;
+            if let Some(direction) = direction {
+                snake.direction = direction;
+            }
+        }
+    }
+
+    pub fn config(&self) -> &SimConfig {
+        &self.config
+    }
+    // 今の段階での 1 tick の長さ
+    pub fn tick_duration(&self) -> Duration {
+        self.current.tick()
+    }
+    // 各 tick をその時の速さで数えた経過時間
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+    pub fn seed(&self) -> u64 {
+        self.rng.seed()
+    }
+    pub fn inputs(&self) -> &[Option<Direction>] {
+        &self.inputs
+    }
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+    pub fn is_over(&self) -> bool {
+        self.game_over
+    }
+    pub fn snakes(&self) -> impl Iterator<Item = &SimSnake> {
+        self.snakes.iter()
+    }
+    pub fn snake(&self, id: SnakeId) -> Option<&SimSnake> {
+        self.snakes.iter().find(|snake| snake.id == id)
+    }
+    pub fn player(&self) -> Option<&SimSnake> {
+        self.snakes.iter().find(|snake| snake.is_player())
+    }
+    pub fn enemy_count(&self) -> usize {
+        self.snakes
+            .iter()
+            .filter(|snake| !snake.is_player())
+            .count()
+    }
+    pub fn foods(&self) -> impl Iterator<Item = &SimFood> {
+        self.foods.iter()
+    }
+    pub fn score(&self) -> &PlayerScore {
+        &self.score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // body は頭から。頭は direction に向かって進む
+    fn snake(id: u32, kind: SnakeKind, direction: Direction, body: &[(i32, i32)]) -> SimSnake {
+        let body = body.iter().map(|&(x, y)| Position::new(x, y)).collect();
+        let mut snake = SimSnake::new(SnakeId(id), kind, body, None);
+        snake.direction = direction;
+        snake
+    }
+
+    fn enemy(id: u32, direction: Direction, body: &[(i32, i32)]) -> SimSnake {
+        snake(id, SnakeKind::Enemy, direction, body)
+    }
+
+    fn food(x: i32, y: i32) -> SimFood {
+        SimFood {
+            id: FoodId(100),
+            pos: Position::new(x, y),
+        }
+    }
+
+    fn to(x: i32, y: i32, eats: bool) -> Move {
+        Move::To {
+            head: Position::new(x, y),
+            eats,
+        }
+    }
+
+    fn resolve(snakes: &[SimSnake], foods: &[SimFood]) -> Vec<Move> {
+        resolve_moves(Arena::default(), snakes, foods)
+    }
+
+    #[test]
+    fn heads_entering_the_same_cell_both_die() {
+        let snakes = [
+            enemy(0, Direction::RIGHT, &[(9, 10), (8, 10)]),
+            enemy(1, Direction::LEFT, &[(11, 10), (12, 10)]),
+        ];
+        assert_eq!(resolve(&snakes, &[]), vec![Move::Dead, Move::Dead]);
+    }
+
+    #[test]
+    fn food_taken_head_on_is_not_eaten() {
+        let snakes = [
+            enemy(0, Direction::UP, &[(10, 9), (10, 8)]),
+            enemy(1, Direction::DOWN, &[(10, 11), (10, 12)]),
+        ];
+        assert_eq!(
+            resolve(&snakes, &[food(10, 10)]),
+            vec![Move::Dead, Move::Dead]
+        );
+    }
+
+    #[test]
+    fn heads_passing_through_each_other_both_die() {
+        let snakes = [
+            enemy(0, Direction::RIGHT, &[(10, 10), (9, 10)]),
+            enemy(1, Direction::LEFT, &[(11, 10), (12, 10)]),
+        ];
+        assert_eq!(resolve(&snakes, &[]), vec![Move::Dead, Move::Dead]);
+    }
+
+    #[test]
+    fn head_into_a_body_dies_and_the_other_moves_on() {
+        let snakes = [
+            enemy(0, Direction::UP, &[(10, 9), (10, 8)]),
+            enemy(1, Direction::RIGHT, &[(11, 10), (10, 10), (9, 10)]),
+        ];
+        assert_eq!(resolve(&snakes, &[]), vec![Move::Dead, to(12, 10, false)]);
+    }
+
+    #[test]
+    fn head_may_follow_a_tail_that_is_moving_away() {
+        let snakes = [
+            enemy(0, Direction::UP, &[(9, 9), (9, 8)]),
+            enemy(1, Direction::RIGHT, &[(11, 10), (10, 10), (9, 10)]),
+        ];
+        assert_eq!(
+            resolve(&snakes, &[]),
+            vec![to(9, 10, false), to(12, 10, false)]
+        );
+    }
+
+    #[test]
+    fn snake_may_chase_its_own_tail() {
+        let snakes = [enemy(
+            0,
+            Direction::DOWN,
+            &[(10, 11), (11, 11), (11, 10), (10, 10)],
+        )];
+        assert_eq!(resolve(&snakes, &[]), vec![to(10, 10, false)]);
+    }
+
+    #[test]
+    fn tail_of_a_growing_snake_stays() {
+        let snakes = [
+            enemy(0, Direction::UP, &[(9, 9), (9, 8)]),
+            enemy(1, Direction::RIGHT, &[(11, 10), (10, 10), (9, 10)]),
+        ];
+        assert_eq!(
+            resolve(&snakes, &[food(12, 10)]),
+            vec![Move::Dead, to(12, 10, true)]
+        );
+    }
+
+    #[test]
+    fn tail_of_a_snake_dying_head_on_is_free() {
+        let snakes = [
+            enemy(0, Direction::UP, &[(9, 9), (9, 8)]),
+            // 向かいの敵と餌の上で正面衝突するので伸びない
+            enemy(1, Direction::RIGHT, &[(11, 10), (10, 10), (9, 10)]),
+            enemy(2, Direction::LEFT, &[(13, 10), (14, 10)]),
+        ];
+        assert_eq!(
+            resolve(&snakes, &[food(12, 10)]),
+            vec![to(9, 10, false), Move::Dead, Move::Dead]
+        );
+    }
+
+    // tick ごとの餌の位置と全員の体
+    fn play(seed: u64) -> Vec<(Vec<Position>, Vec<Vec<Position>>)> {
+        let mut sim = Simulation::new(SimConfig::default(), seed);
+        let mut frames = Vec::new();
+        for tick in 0..300 {
+            let player = (tick % 7 == 0).then(|| Direction::num2dir((tick / 7 % 4) as u8));
+            sim.step(StepInput { player });
+            frames.push((
+                sim.foods().map(SimFood::pos).collect(),
+                sim.snakes().map(|snake| snake.body().to_vec()).collect(),
+            ));
+        }
+        frames
+    }
+
+    #[test]
+    fn same_seed_and_inputs_give_the_same_game() {
+        assert_eq!(play(42), play(42));
+        assert_ne!(play(42), play(43));
+    }
+
+    #[test]
+    fn player_leaving_the_arena_dies() {
+        let snakes = [snake(
+            0,
+            SnakeKind::Player,
+            Direction::LEFT,
+            &[(0, 5), (1, 5)],
+        )];
+        assert_eq!(resolve(&snakes, &[]), vec![Move::Dead]);
+    }
+
+    #[test]
+    fn validate_names_the_broken_stage() {
+        let config = SimConfig {
+            stages: vec![
+                Stage {
+                    food: 3,
+                    ..Stage::default()
+                },
+                Stage {
+                    food: 5,
+                    speed: Some(0),
+                    ..Stage::default()
+                },
+            ],
+            ..SimConfig::default()
+        };
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError {
+                stage: Some(1),
+                field: "speed",
+                reason: "must be between 1 and 600",
+            })
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use rand::Rng;
+
+use crate::components::Position;
+use crate::constants::DIGIT_SIZE;
+use crate::resources::{Arena, PlayerScore};
+
+// 横 4 x 縦 6 の格子点 (上の行が 0) を縦横にだけ結ぶ一筆書き。ヘビがたどれるよう逆戻りしない
+const TEMPLATES: [&[(i32, i32)]; 10] = [
+    &[(0, 0), (4, 0), (4, 6), (0, 6), (0, 0)],
+    &[(1, 1), (1, 0), (2, 0), (2, 6)],
+    &[(0, 0), (4, 0), (4, 3), (0, 3), (0, 6), (4, 6)],
+    &[
+        (0, 0),
+        (4, 0),
+        (4, 3),
+        (1, 3),
+        (1, 4),
+        (4, 4),
+        (4, 6),
+        (0, 6),
+    ],
+    &[(0, 0), (0, 3), (4, 3), (4, 0), (3, 0), (3, 6)],
+    &[(4, 0), (0, 0), (0, 3), (4, 3), (4, 6), (0, 6)],
+    &[(4, 0), (0, 0), (0, 6), (4, 6), (4, 3), (0, 3)],
+    &[(0, 0), (4, 0), (4, 6)],
+    &[(0, 3), (0, 0), (4, 0), (4, 6), (0, 6), (0, 3), (4, 3)],
+    &[(4, 3), (0, 3), (0, 0), (4, 0), (4, 6), (0, 6)],
+];
+
+// 枠からの余白 (セル)
+const MARGIN: i32 = 2;
+
+/// 数字をヘビの軌跡として描き、ゲームと同じく `PlayerScore` に積んで返す
+pub fn draw<R: Rng>(digit: u8, rng: &mut R) -> [[f32; DIGIT_SIZE]; DIGIT_SIZE] {
+    let arena = Arena::default();
+    let mut score = PlayerScore::new(arena);
+    let half = rng.gen_range(0..2);
+    let half_width = arena.width() as i32 / 2;
+    let height = arena.height() as i32;
+
+    let w = rng.gen_range(half_width / 4..=half_width * 3 / 5);
+    let h = rng.gen_range(height / 2..=height - 2 * MARGIN);
+    let x0 = half * half_width + rng.gen_range(MARGIN..=half_width - MARGIN - w);
+    let y0 = rng.gen_range(MARGIN..=height - MARGIN - h);
+
+    // 格子の各列・各行をまとめてずらし、線が縦横のまま揺らぐようにする
+    let xs = (0..=4)
+        .map(|i| (x0 + i * w / 4 + rng.gen_range(-1..=1)).clamp(x0, x0 + w))
+        .collect::<Vec<i32>>();
+    let ys = (0..=6)
+        .map(|i| (y0 + h - i * h / 6 + rng.gen_range(-1..=1)).clamp(y0, y0 + h))
+        .collect::<Vec<i32>>();
+    let points = TEMPLATES[digit as usize % 10]
+        .iter()
+        .map(|&(x, y)| Position::new(xs[x as usize], ys[y as usize]))
+        .collect::<Vec<Position>>();
+
+    // 半分くらいはどこかから書き始めの点まで這ってくる
+    let mut head = if rng.gen_bool(0.5) {
+        Position::new(
+            half * half_width + rng.gen_range(0..half_width),
+            rng.gen_range(0..height),
+        )
+    } else {
+        points[0]
+    };
+    score.add(head);
+    for &target in points.iter() {
+        // 縦と横のどちらを先に進むかは都度決める
+        let corner = if rng.gen_bool(0.5) {
+            Position::new(target.x(), head.y())
+        } else {
+            Position::new(head.x(), target.y())
+        };
+        for to in [corner, target] {
+            while head != to {
+                let step = to - head;
+                head += Position::new(step.x().signum(), step.y().signum());
+                score.add(head);
+            }
+        }
+    }
+    score.digit_grid(half as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_never_turn_back() {
+        for (digit, template) in TEMPLATES.iter().enumerate() {
+            let steps = template
+                .windows(2)
+                .map(|pair| ((pair[1].0 - pair[0].0), (pair[1].1 - pair[0].1)))
+                .collect::<Vec<_>>();
+            for &(dx, dy) in &steps {
+                assert!(
+                    (dx == 0) != (dy == 0),
+                    "digit {digit} has a segment that is not horizontal or vertical"
+                );
+            }
+            for pair in steps.windows(2) {
+                let (a, b) = (pair[0], pair[1]);
+                assert!(
+                    a.0.signum() != -b.0.signum() || a.1.signum() != -b.1.signum(),
+                    "digit {digit} turns back on itself"
+                );
+            }
+        }
+    }
+}
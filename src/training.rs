@@ -1,6 +1,67 @@
-use burn::{config::Config, optim::AdamConfig};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
+#[cfg(feature = "candle")]
+use burn::backend::Candle;
+#[cfg(feature = "ndarray")]
+use burn::backend::NdArray;
+#[cfg(feature = "wgpu")]
+use burn::backend::Wgpu;
+use burn::{
+    backend::Autodiff,
+    config::Config,
+    data::{
+        dataloader::{DataLoader, DataLoaderBuilder},
+        dataset::vision::MnistDataset,
+    },
+    module::Module,
+    optim::AdamConfig,
+    record::{CompactRecorder, Recorder},
+    tensor::backend::AutodiffBackend,
+    train::{
+        metric::{AccuracyMetric, LossMetric},
+        LearnerBuilder,
+    },
+};
+
+use crate::data::{
+    DrawingDataset, MixedDataset, MnistBatch, MnistBatcher, StrokeBatcher, StrokeDataset,
+};
+use crate::drawings::{self, Drawing};
+use crate::inference::{BackendKind, ModelError};
+use crate::manifest::{self, manifest_path, Manifest};
 use crate::model::ModelConfig;
+use crate::preprocess::Preprocess;
+
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TrainData {
+    /// MNIST only
+    Mnist,
+    /// MNIST followed by synthetic snake strokes
+    Mixed,
+    /// Fine-tune the trained model on synthetic snake strokes only
+    Strokes,
+    /// Fine-tune the trained model on drawings labelled on the score screen
+    Drawings,
+}
+
+impl TrainData {
+    // 学習済みの重みから始めるもの
+    fn is_finetune(self) -> bool {
+        matches!(self, TrainData::Strokes | TrainData::Drawings)
+    }
+    fn name(self) -> &'static str {
+        match self {
+            TrainData::Mnist => "mnist",
+            TrainData::Mixed => "mixed",
+            TrainData::Strokes => "strokes",
+            TrainData::Drawings => "drawings",
+        }
+    }
+}
 
 #[derive(Config)]
 pub struct TrainingConfig {
@@ -16,4 +77,322 @@ pub struct TrainingConfig {
     pub seed: u64,
     #[config(default = 1.0e-4)]
     pub learning_rate: f64,
+    // ヘビの軌跡に掛ける前処理。推論時もこれに従う
+    #[config(default = "Preprocess::default()")]
+    pub preprocess: Preprocess,
+}
+
+// 推論側と同じ配置: {dir}/{dir の最後の名前}.config.json と {dir}/model.mpk
+pub fn config_path(artifact_dir: &str) -> PathBuf {
+    let dir = Path::new(artifact_dir);
+    let name = dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("model");
+    dir.join(format!("{name}.config.json"))
+}
+// CompactRecorder は拡張子 .mpk を補う
+pub fn model_path(artifact_dir: &str) -> PathBuf {
+    Path::new(artifact_dir).join("model")
+}
+
+// checkpoint/model-{epoch}.mpk のうち最新のもの
+fn latest_checkpoint(artifact_dir: &str) -> Option<usize> {
+    fs::read_dir(format!("{artifact_dir}/checkpoint"))
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            entry
+                .file_name()
+                .to_str()?
+                .strip_prefix("model-")?
+                .strip_suffix(".mpk")?
+                .parse()
+                .ok()
+        })
+        .max()
+}
+
+// 検証用の合成データは学習用の 1/VALID_STROKE_RATIO。これより少ないと検証用が空になる
+pub const VALID_STROKE_RATIO: usize = 6;
+
+fn dataloaders<B: AutodiffBackend>(
+    config: &TrainingConfig,
+    data: TrainData,
+    stroke_count: usize,
+    drawings: Vec<Drawing>,
+    device: &B::Device,
+) -> (
+    Arc<dyn DataLoader<MnistBatch<B>>>,
+    Arc<dyn DataLoader<MnistBatch<B::InnerBackend>>>,
+) {
+    let train_strokes = StrokeDataset::new(config.seed, stroke_count);
+    let valid_strokes = StrokeDataset::new(config.seed + 1, stroke_count / VALID_STROKE_RATIO);
+    match data {
+        TrainData::Mnist | TrainData::Mixed => {
+            let train = DataLoaderBuilder::new(MnistBatcher::<B>::new(device.clone()))
+                .batch_size(config.batch_size)
+                .shuffle(config.seed)
+                .num_workers(config.num_workers);
+            let valid =
+                DataLoaderBuilder::new(MnistBatcher::<B::InnerBackend>::new(device.clone()))
+                    .batch_size(config.batch_size)
+                    .shuffle(config.seed)
+                    .num_workers(config.num_workers);
+            if data == TrainData::Mixed {
+                (
+                    train.build(MixedDataset::new(
+                        MnistDataset::train(),
+                        train_strokes,
+                        config.preprocess,
+                    )),
+                    valid.build(MixedDataset::new(
+                        MnistDataset::test(),
+                        valid_strokes,
+                        config.preprocess,
+                    )),
+                )
+            } else {
+                (
+                    train.build(MnistDataset::train()),
+                    valid.build(MnistDataset::test()),
+                )
+            }
+        }
+        TrainData::Strokes | TrainData::Drawings => {
+            let train =
+                DataLoaderBuilder::new(StrokeBatcher::<B>::new(device.clone(), config.preprocess))
+                    .batch_size(config.batch_size)
+                    .shuffle(config.seed)
+                    .num_workers(config.num_workers);
+            let valid = DataLoaderBuilder::new(StrokeBatcher::<B::InnerBackend>::new(
+                device.clone(),
+                config.preprocess,
+            ))
+            .batch_size(config.batch_size)
+            .shuffle(config.seed)
+            .num_workers(config.num_workers);
+            if data == TrainData::Drawings {
+                let (train_drawings, valid_drawings) =
+                    DrawingDataset::new(drawings).split(VALID_EVERY);
+                (train.build(train_drawings), valid.build(valid_drawings))
+            } else {
+                (train.build(train_strokes), valid.build(valid_strokes))
+            }
+        }
+    }
+}
+
+// 集めた軌跡は 6 件に 1 件を検証用にする
+const VALID_EVERY: usize = 6;
+
+// MNIST 以外の学習はチェックポイントやログを MNIST の学習と分けて置く。
+// できた重みはどれも artifact_dir に書く
+fn learner_dir(artifact_dir: &str, data: TrainData) -> String {
+    match data {
+        TrainData::Mnist => artifact_dir.to_string(),
+        TrainData::Mixed => format!("{artifact_dir}/mixed"),
+        TrainData::Strokes => format!("{artifact_dir}/finetune"),
+        TrainData::Drawings => format!("{artifact_dir}/finetune-drawings"),
+    }
+}
+
+pub fn train<B: AutodiffBackend>(
+    artifact_dir: &str,
+    config: TrainingConfig,
+    options: &TrainOptions,
+    drawings: Vec<Drawing>,
+    checkpoint: Option<usize>,
+    device: B::Device,
+) -> Result<(), ModelError> {
+    fs::create_dir_all(artifact_dir)?;
+
+    B::seed(config.seed);
+
+    let (dataloader_train, dataloader_valid) =
+        dataloaders::<B>(&config, options.data, options.strokes, drawings, &device);
+
+    let mut model = config.model.init::<B>(&device);
+    // 追加学習は学習済みの重みから始める
+    if options.data.is_finetune() && checkpoint.is_none() {
+        let record = CompactRecorder::new()
+            .load(model_path(artifact_dir), &device)
+            .map_err(|err| ModelError::Weights(err.to_string()))?;
+        model = model.load_record(record);
+    }
+
+    let mut builder = LearnerBuilder::new(&learner_dir(artifact_dir, options.data))
+        .metric_train_numeric(AccuracyMetric::new())
+        .metric_valid_numeric(AccuracyMetric::new())
+        .metric_train_numeric(LossMetric::new())
+        .metric_valid_numeric(LossMetric::new())
+        .with_file_checkpointer(CompactRecorder::new())
+        .devices(vec![device.clone()])
+        .num_epochs(config.num_epochs)
+        .summary();
+    if let Some(epoch) = checkpoint {
+        builder = builder.checkpoint(epoch);
+    }
+    let learner = builder.build(model, config.optimizer.init(), config.learning_rate);
+
+    let model_trained = learner.fit(dataloader_train, dataloader_valid);
+
+    model_trained
+        .save_file(model_path(artifact_dir), &CompactRecorder::new())
+        .map_err(|err| ModelError::Save(err.to_string()))?;
+    // 途中で失敗したときに、前のモデルの設定を上書きしないよう最後に書く
+    config.save(config_path(artifact_dir))?;
+    Ok(())
+}
+
+pub struct TrainOptions {
+    pub epochs: Option<usize>,
+    pub batch_size: Option<usize>,
+    pub learning_rate: Option<f64>,
+    pub data: TrainData,
+    // 合成データの数
+    pub strokes: usize,
+    // `--data drawings` で読むファイル
+    pub drawings: Option<PathBuf>,
+    // 最新のチェックポイントから続ける
+    pub resume: bool,
+}
+
+/// 既存の設定を読み、指定された項目だけ上書きして学習する
+pub fn run(artifact_dir: &str, options: &TrainOptions) -> Result<(), ModelError> {
+    let path = config_path(artifact_dir);
+    let mut config = if path.exists() {
+        TrainingConfig::load(&path).map_err(|err| ModelError::Config(err.to_string()))?
+    } else {
+        TrainingConfig::new(ModelConfig::new(10, 512), AdamConfig::new())
+    };
+    if let Some(epochs) = options.epochs {
+        config.num_epochs = epochs;
+    }
+    if let Some(batch_size) = options.batch_size {
+        config.batch_size = batch_size;
+    }
+    if let Some(learning_rate) = options.learning_rate {
+        config.learning_rate = learning_rate;
+    }
+
+    let checkpoint = if options.resume {
+        latest_checkpoint(&learner_dir(artifact_dir, options.data))
+    } else {
+        None
+    };
+    if let Some(epoch) = checkpoint {
+        if epoch >= config.num_epochs {
+            println!(
+                "already trained for {epoch} epochs; pass --epochs to train further or --fresh to start over"
+            );
+            return Ok(());
+        }
+        println!("resuming from epoch {epoch}");
+    }
+    let weights = model_path(artifact_dir).with_extension("mpk");
+    if options.data.is_finetune() && checkpoint.is_none() && !weights.exists() {
+        return Err(ModelError::Missing(weights));
+    }
+    let drawings = if options.data == TrainData::Drawings {
+        let path = options
+            .drawings
+            .as_deref()
+            .ok_or_else(|| ModelError::Data("no drawings file given".to_string()))?;
+        load_drawings(path)?
+    } else {
+        Vec::new()
+    };
+
+    let manifest = Manifest::new(
+        options.data.name(),
+        dataset_hash(&config, options, &drawings),
+    );
+
+    let kind = BackendKind::detect().ok_or(ModelError::NoBackend)?;
+    println!("training on {}", kind.name());
+    match kind {
+        #[cfg(feature = "wgpu")]
+        BackendKind::Wgpu => train::<Autodiff<Wgpu>>(
+            artifact_dir,
+            config,
+            options,
+            drawings,
+            checkpoint,
+            Default::default(),
+        ),
+        #[cfg(feature = "ndarray")]
+        BackendKind::NdArray => train::<Autodiff<NdArray>>(
+            artifact_dir,
+            config,
+            options,
+            drawings,
+            checkpoint,
+            Default::default(),
+        ),
+        #[cfg(feature = "candle")]
+        BackendKind::Candle => train::<Autodiff<Candle>>(
+            artifact_dir,
+            config,
+            options,
+            drawings,
+            checkpoint,
+            Default::default(),
+        ),
+    }?;
+    manifest.save(&manifest_path(artifact_dir))?;
+    Ok(())
+}
+
+// 合成データはシードと数から決まるので、中身の代わりにそれらを混ぜる。
+// MNIST は固定なので名前だけ
+fn dataset_hash(config: &TrainingConfig, options: &TrainOptions, drawings: &[Drawing]) -> u64 {
+    let strokes = [options.strokes as u64, config.seed].map(u64::to_le_bytes);
+    let preprocess = serde_json::to_vec(&config.preprocess).unwrap_or_default();
+    let drawings = drawings
+        .iter()
+        .flat_map(|drawing| serde_json::to_vec(drawing).unwrap_or_default())
+        .collect::<Vec<u8>>();
+    let parts: Vec<&[u8]> = match options.data {
+        TrainData::Mnist => vec![b"mnist"],
+        TrainData::Mixed => vec![b"mnist", &strokes[0], &strokes[1], &preprocess],
+        TrainData::Strokes => vec![&strokes[0], &strokes[1], &preprocess],
+        TrainData::Drawings => vec![&drawings, &preprocess],
+    };
+    manifest::dataset_hash(parts)
+}
+
+// 学習と検証の両方に 1 件以上残る数が要る
+fn load_drawings(path: &Path) -> Result<Vec<Drawing>, ModelError> {
+    let drawings = drawings::load(path)
+        .map_err(|err| ModelError::Data(format!("{}: {err}", path.display())))?;
+    if drawings.len() < VALID_EVERY {
+        return Err(ModelError::Data(format!(
+            "{} has {} labelled drawings; collect at least {VALID_EVERY}",
+            path.display(),
+            drawings.len()
+        )));
+    }
+    println!("fine-tuning on {} drawings", drawings.len());
+    Ok(drawings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn artifact_paths_stay_inside_a_nested_directory() {
+        assert_eq!(
+            config_path("out/run1"),
+            Path::new("out/run1/run1.config.json")
+        );
+        assert_eq!(model_path("out/run1"), Path::new("out/run1/model"));
+    }
+
+    #[test]
+    fn artifact_paths_keep_the_shipped_layout() {
+        assert_eq!(config_path("data"), Path::new("data/data.config.json"));
+        assert_eq!(model_path("data"), Path::new("data/model"));
+    }
 }